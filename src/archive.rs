@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read as _, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use sha2::Digest as _;
+use tokio::sync::mpsc;
+use zip::write::SimpleFileOptions;
+
+/// Returns the path of the archive of an edition with `--zip`, next to its directory.
+pub fn path(edition_dir: &Path) -> PathBuf {
+    with_suffix(edition_dir, ".zip")
+}
+
+/// Returns `path` with a suffix appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+/// Returns the name of the entry of a file in the archive, with `/` separators.
+pub fn entry_name(rel_path: &Path) -> String {
+    rel_path
+        .iter()
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn options() -> SimpleFileOptions {
+    // The Gbx files are already compressed
+    SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(true)
+}
+
+/// A file already in the archive resumed with `--resume`.
+pub struct Existing {
+    pub size: u64,
+    /// Only computed if asked for.
+    pub sha256: Option<String>,
+}
+
+/// Rewrites the complete entries of the archive left by an interrupted run, which has no
+/// central directory, to `tmp`. Returns the number of entries kept.
+fn recover(tmp: &Path) -> anyhow::Result<usize> {
+    let partial = with_suffix(tmp, ".partial");
+    std::fs::rename(tmp, &partial)
+        .with_context(|| format!("Unable to move `{}`", tmp.display()))?;
+    let mut reader = io::BufReader::new(
+        File::open(&partial).with_context(|| format!("Unable to open `{}`", partial.display()))?,
+    );
+    let file =
+        File::create(tmp).with_context(|| format!("Unable to create `{}`", tmp.display()))?;
+    let mut zip = zip::ZipWriter::new(io::BufWriter::new(file));
+    let mut kept = 0;
+    // Reading an entry to its end checks its CRC, the one cut by the interruption fails
+    while let Ok(Some(mut entry)) = zip::read::read_zipfile_from_stream(&mut reader) {
+        let mut content = Vec::new();
+        if entry.read_to_end(&mut content).is_err() {
+            break;
+        }
+        zip.start_file(entry.name()?, options())?;
+        zip.write_all(&content)?;
+        kept += 1;
+    }
+    zip.finish()?.flush()?;
+    std::fs::remove_file(&partial)
+        .with_context(|| format!("Unable to remove `{}`", partial.display()))?;
+    Ok(kept)
+}
+
+/// Prepares `tmp` from the archive of the previous run, or from the temporary archive left by an
+/// interrupted one, and returns it ready for appending with the files it contains.
+fn reopen(
+    path: &Path,
+    tmp: &Path,
+    sha256: bool,
+) -> anyhow::Result<(zip::ZipWriter<File>, HashMap<String, Existing>)> {
+    if path.is_file() {
+        std::fs::copy(path, tmp).with_context(|| format!("Unable to copy `{}`", path.display()))?;
+    } else if tmp.is_file() {
+        let kept = recover(tmp)?;
+        crate::log::info!(
+            "Recovered {kept} file(s) from the interrupted archive `{}`",
+            tmp.display()
+        );
+    } else {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(tmp)
+            .with_context(|| format!("Unable to create archive `{}`", tmp.display()))?;
+        return Ok((zip::ZipWriter::new(file), HashMap::new()));
+    }
+
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .open(tmp)
+        .with_context(|| format!("Unable to open archive `{}`", tmp.display()))?;
+    let mut existing = HashMap::new();
+    let mut archive = zip::ZipArchive::new(&mut file)
+        .with_context(|| format!("Unable to read archive `{}`", tmp.display()))?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let sha256 = if sha256 {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            Some(
+                sha2::Sha256::digest(&content)
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        existing.insert(
+            entry.name()?.into_owned(),
+            Existing {
+                size: entry.size(),
+                sha256,
+            },
+        );
+    }
+    drop(archive);
+    let zip = zip::ZipWriter::new_append(file)
+        .with_context(|| format!("Unable to append to archive `{}`", tmp.display()))?;
+    Ok((zip, existing))
+}
+
+/// Writes the maps of an edition to a zip archive as they're downloaded.
+///
+/// The archive is written by a single blocking task receiving the files through a bounded
+/// channel, so that the maps don't all wait in memory. It's written to a temporary file, only
+/// renamed once the archive is finished.
+pub struct Writer {
+    path: PathBuf,
+    tmp: PathBuf,
+    tx: mpsc::Sender<(String, bytes::Bytes)>,
+    task: tokio::task::JoinHandle<anyhow::Result<()>>,
+}
+
+impl Writer {
+    pub fn create(path: PathBuf, capacity: usize) -> anyhow::Result<Self> {
+        let tmp = with_suffix(&path, ".tmp");
+        let file = File::create(&tmp)
+            .with_context(|| format!("Unable to create archive `{}`", tmp.display()))?;
+        let zip = zip::ZipWriter::new(io::BufWriter::new(file));
+        Ok(Self::spawn(path, tmp, zip, capacity))
+    }
+
+    /// Resumes the archive of a previous run, keeping its files, which are returned by entry
+    /// name with their SHA-256 if asked for.
+    ///
+    /// The archive left by an interrupted run has no central directory, its complete entries are
+    /// copied to a new archive.
+    pub async fn resume(
+        path: PathBuf,
+        capacity: usize,
+        sha256: bool,
+    ) -> anyhow::Result<(Self, HashMap<String, Existing>)> {
+        let tmp = with_suffix(&path, ".tmp");
+        let (zip, existing) = tokio::task::spawn_blocking({
+            let (path, tmp) = (path.clone(), tmp.clone());
+            move || reopen(&path, &tmp, sha256)
+        })
+        .await
+        .context("Archive task panicked")??;
+        Ok((Self::spawn(path, tmp, zip, capacity), existing))
+    }
+
+    fn spawn<W: Write + Seek + Send + 'static>(
+        path: PathBuf,
+        tmp: PathBuf,
+        mut zip: zip::ZipWriter<W>,
+        capacity: usize,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::channel::<(String, bytes::Bytes)>(capacity);
+        let task_tmp = tmp.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let res = (|| {
+                while let Some((name, content)) = rx.blocking_recv() {
+                    zip.start_file(name, options())?;
+                    zip.write_all(&content)?;
+                }
+                zip.finish()?.flush()?;
+                anyhow::Ok(())
+            })();
+            res.with_context(|| format!("Unable to write archive `{}`", task_tmp.display()))
+        });
+        Self {
+            path,
+            tmp,
+            tx,
+            task,
+        }
+    }
+
+    /// Adds a file to the archive, waiting if the writer is behind.
+    pub async fn add(&self, rel_path: &Path, content: bytes::Bytes) -> anyhow::Result<()> {
+        if self.tx.send((entry_name(rel_path), content)).await.is_err() {
+            // The task stopped on an error, returned by `finish`
+            anyhow::bail!("Unable to write archive `{}`", self.tmp.display());
+        }
+        Ok(())
+    }
+
+    pub async fn finish(self) -> anyhow::Result<PathBuf> {
+        drop(self.tx);
+        self.task.await.context("Archive task panicked")??;
+        std::fs::rename(&self.tmp, &self.path)
+            .with_context(|| format!("Unable to write archive `{}`", self.path.display()))?;
+        Ok(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("soevent-{test}-{}.zip", std::process::id()))
+    }
+
+    fn entries(path: &Path) -> Vec<(String, zip::CompressionMethod, Vec<u8>)> {
+        let mut archive = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut entry = archive.by_index(i).unwrap();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (
+                    entry.name().unwrap().into_owned(),
+                    entry.compression(),
+                    content,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn writes_stored_entries_in_order() {
+        let path = temp_path("archive");
+        let writer = Writer::create(path.clone(), 1).unwrap();
+        let files = [
+            "white/uidB.Map.Gbx",
+            "white/uidA.Map.Gbx",
+            "green/uidC.Map.Gbx",
+        ];
+        for name in files {
+            writer
+                .add(Path::new(name), bytes::Bytes::from(name))
+                .await
+                .unwrap();
+        }
+        assert_eq!(writer.finish().await.unwrap(), path);
+
+        let expected = files
+            .iter()
+            .map(|name| {
+                let content = name.as_bytes().to_vec();
+                (name.to_string(), zip::CompressionMethod::Stored, content)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(entries(&path), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// The archive of an interrupted run has no central directory and its last entry is cut
+    #[tokio::test]
+    async fn resumes_interrupted_archive() {
+        let path = temp_path("archive-resume");
+        let tmp = with_suffix(&path, ".tmp");
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, len) in [("a.Map.Gbx", 100), ("b.Map.Gbx", 200), ("c.Map.Gbx", 300)] {
+            zip.start_file(name, options()).unwrap();
+            zip.write_all(&vec![len as u8; len]).unwrap();
+        }
+        let mut content = zip.finish().unwrap().into_inner();
+        let cut = zip::ZipArchive::new(io::Cursor::new(&content))
+            .unwrap()
+            .by_index(2)
+            .unwrap()
+            .data_start()
+            .unwrap()
+            + 10;
+        content.truncate(cut as usize);
+        std::fs::write(&tmp, content).unwrap();
+
+        let (writer, existing) = Writer::resume(path.clone(), 1, true).await.unwrap();
+        let mut names = existing.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a.Map.Gbx", "b.Map.Gbx"]);
+        assert_eq!(existing["b.Map.Gbx"].size, 200);
+        writer
+            .add(Path::new("c.Map.Gbx"), bytes::Bytes::from(vec![3; 300]))
+            .await
+            .unwrap();
+        writer.finish().await.unwrap();
+
+        let expected = [
+            ("a.Map.Gbx", 100, 100),
+            ("b.Map.Gbx", 200, 200),
+            ("c.Map.Gbx", 3, 300),
+        ]
+        .into_iter()
+        .map(|(name, byte, len)| {
+            let content = vec![byte as u8; len];
+            (name.to_owned(), zip::CompressionMethod::Stored, content)
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(entries(&path), expected);
+        assert!(!tmp.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use anyhow::Context as _;
+
+use futures::StreamExt as _;
+
+use crate::log;
+use crate::stats::Stats;
+use crate::Context;
+
+struct Entry {
+    line: usize,
+    handle: String,
+    edition: Option<u32>,
+}
+
+fn parse(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read batch file `{}`", path.display()))?;
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line, content)| {
+            let mut words = content.split_whitespace();
+            let handle = words.next().unwrap_or_default().to_owned();
+            let edition = words
+                .next()
+                .map(str::parse)
+                .transpose()
+                .with_context(|| format!("Invalid edition ID at line {line}"))?;
+            anyhow::ensure!(
+                words.next().is_none(),
+                "Unexpected trailing content at line {line}"
+            );
+            Ok(Entry {
+                line,
+                handle,
+                edition,
+            })
+        })
+        .collect()
+}
+
+pub async fn run(ctx: &Context, path: &Path) -> anyhow::Result<Stats> {
+    let entries = parse(path)?;
+    log::info!("Downloading {} batch entries...", entries.len());
+
+    let results = futures::stream::iter(entries)
+        .map(|entry| async move {
+            let res = async {
+                anyhow::ensure!(
+                    !ctx.disk_full.load(Ordering::Relaxed),
+                    "Skipped, the disk is full"
+                );
+                let (handle, edition) =
+                    crate::resolve_edition(ctx, Some(entry.handle.clone()), entry.edition).await?;
+                let stats = crate::process_edition(ctx, &handle, edition).await?;
+                anyhow::Ok((edition, stats))
+            }
+            .await;
+            if let Err(e) = &res {
+                log::error!("Batch entry `{}` failed: {e:#}", entry.handle);
+            }
+            (entry, res)
+        })
+        .buffered(ctx.args.parallel_editions as _)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut total = Stats::default();
+    let mut failed = 0;
+    for (entry, res) in &results {
+        match res {
+            Ok((edition, stats)) => {
+                total += *stats;
+                log::info!(
+                    "[ok]   line {}: {} edition {edition}: {stats}",
+                    entry.line,
+                    entry.handle
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                ctx.failures.record(e);
+                log::error!("[fail] line {}: {}: {e:#}", entry.line, entry.handle);
+            }
+        }
+    }
+
+    if failed > 0 {
+        log::error!("{failed} out of {} batch entries failed", results.len());
+    }
+    total.failed += failed;
+    Ok(total)
+}
@@ -0,0 +1,34 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+
+/// Returns the path of the cached API response of an event edition.
+pub fn edition_path(out: &str, event_handle: &str, event_edition: u32) -> PathBuf {
+    Path::new(out)
+        .join(".soevent")
+        .join("cache")
+        .join(event_handle)
+        .join(format!("{event_edition}.json"))
+}
+
+/// Returns the path of the file recording the last edition of an event downloaded with
+/// `--refresh-latest`.
+pub fn latest_path(out: &str, event_handle: &str) -> PathBuf {
+    Path::new(out)
+        .join(".soevent")
+        .join("latest")
+        .join(event_handle)
+}
+
+pub fn store(path: &Path, body: &str) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create cache directory `{}`", parent.display()))?;
+    }
+    crate::output::write_file(path, body)
+}
+
+pub fn load(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read cached response `{}`", path.display()))
+}
@@ -0,0 +1,125 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use sha2::Digest as _;
+
+use crate::log;
+
+/// A content-addressed store of map files.
+///
+/// The maps are stored once under `objects/<sha256>.Map.Gbx`, and `uids/<map_uid>`, or
+/// `uids/<map_uid>@<mx_version>` when MX gives the version, contains the hash of the content of
+/// a map, so that it isn't downloaded again.
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        for sub in ["objects", "uids"] {
+            let path = dir.join(sub);
+            std::fs::create_dir_all(&path).with_context(|| {
+                format!("Unable to create store directory `{}`", path.display())
+            })?;
+        }
+        // Absolute, so that the links are valid wherever they are created
+        let dir = std::fs::canonicalize(dir)
+            .with_context(|| format!("Unable to resolve store path `{}`", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("objects").join(format!("{sha256}.Map.Gbx"))
+    }
+
+    fn uid_path(&self, map: &crate::Map) -> PathBuf {
+        let name = match map.mx_version {
+            Some(version) => format!("{}@{version}", map.map_uid),
+            None => map.map_uid.clone(),
+        };
+        self.dir.join("uids").join(name)
+    }
+
+    /// Returns the hash and the content of the stored version of the map, if any.
+    ///
+    /// A stored map whose content doesn't have the SHA-256 given by the API is ignored, to be
+    /// downloaded again.
+    pub fn get(&self, map: &crate::Map) -> anyhow::Result<Option<(String, bytes::Bytes)>> {
+        let sha256 = match std::fs::read_to_string(self.uid_path(map)) {
+            Ok(sha256) => sha256.trim().to_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Unable to read the store index"),
+        };
+        match std::fs::read(self.object_path(&sha256)) {
+            Ok(content) => {
+                if let Some(expected) = &map.sha256 {
+                    let actual = sha2::Sha256::digest(&content)
+                        .iter()
+                        .map(|b| format!("{b:02x}"))
+                        .collect::<String>();
+                    if !expected.eq_ignore_ascii_case(&actual) {
+                        log::warn!(
+                            "The stored map has the SHA-256 {actual} instead of {expected}, \
+                             downloading it again"
+                        );
+                        return Ok(None);
+                    }
+                }
+                Ok(Some((sha256, content.into())))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context("Unable to read the stored map"),
+        }
+    }
+
+    /// Stores the map if it isn't already, and returns the path of the stored file.
+    pub fn insert(
+        &self,
+        map: &crate::Map,
+        sha256: &str,
+        content: &[u8],
+        file_mode: Option<u32>,
+    ) -> anyhow::Result<PathBuf> {
+        let object = self.object_path(sha256);
+        // Also replaces an object damaged since it was stored
+        if std::fs::read(&object).ok().as_deref() != Some(content) {
+            crate::output::write_file(&object, content)?;
+            crate::perms::set_mode(&object, file_mode)?;
+        }
+        crate::output::write_file(&self.uid_path(map), sha256)?;
+        Ok(object)
+    }
+}
+
+/// Creates a symbolic link at `link` pointing to `target`, replacing the existing file.
+pub fn link(target: &Path, link: &Path) -> anyhow::Result<()> {
+    match std::fs::remove_file(link) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Unable to replace `{}`", link.display()))
+        }
+        _ => (),
+    }
+    symlink(target, link).with_context(|| {
+        format!(
+            "Unable to link `{}` to `{}`",
+            link.display(),
+            target.display()
+        )
+    })
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,55 @@
+use anyhow::Context as _;
+
+use crate::{fetch_edition, resolve_edition, Context};
+
+/// Prints the categories of the edition with their number of maps.
+pub async fn run(
+    ctx: &Context,
+    event_handle: Option<String>,
+    event_edition: Option<u32>,
+    json: bool,
+) -> anyhow::Result<()> {
+    #[derive(serde::Serialize)]
+    struct CategoryMaps<'a> {
+        handle: &'a str,
+        maps: usize,
+    }
+    #[derive(serde::Serialize)]
+    struct Categories<'a> {
+        event: &'a str,
+        edition: u32,
+        categories: Vec<CategoryMaps<'a>>,
+    }
+
+    let (event_handle, event_edition) = resolve_edition(ctx, event_handle, event_edition).await?;
+    let event = fetch_edition(ctx, &event_handle, event_edition, None).await?;
+    let categories = event
+        .categories
+        .iter()
+        .map(|cat| CategoryMaps {
+            handle: &cat.handle,
+            maps: cat.maps.len(),
+        })
+        .collect::<Vec<_>>();
+
+    if json {
+        let categories = Categories {
+            event: &event_handle,
+            edition: event_edition,
+            categories,
+        };
+        let json =
+            serde_json::to_string(&categories).context("Unable to serialize the categories")?;
+        println!("{json}");
+    } else {
+        let width = categories
+            .iter()
+            .map(|cat| cat.handle.len())
+            .max()
+            .unwrap_or_default();
+        for cat in &categories {
+            println!("{:<width$}  {} map(s)", cat.handle, cat.maps);
+        }
+    }
+    Ok(())
+}
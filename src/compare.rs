@@ -0,0 +1,139 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Context as _;
+
+use crate::{imp, Context, EventEdition};
+
+#[derive(serde::Serialize)]
+struct Change {
+    map_uid: String,
+    categories: BTreeSet<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Move {
+    map_uid: String,
+    from: BTreeSet<String>,
+    to: BTreeSet<String>,
+}
+
+/// The maps which changed from an edition to another.
+#[derive(serde::Serialize)]
+struct Diff {
+    event: String,
+    from: u32,
+    to: u32,
+    added: Vec<Change>,
+    removed: Vec<Change>,
+    moved: Vec<Move>,
+}
+
+/// Returns the categories of each map of the edition.
+fn categories_by_uid(event: &EventEdition) -> BTreeMap<&str, BTreeSet<String>> {
+    let mut maps = BTreeMap::<_, BTreeSet<_>>::new();
+    for cat in &event.categories {
+        for map in &cat.maps {
+            maps.entry(map.map_uid.as_str())
+                .or_default()
+                .insert(cat.handle.clone());
+        }
+    }
+    maps
+}
+
+impl Diff {
+    fn new(event: &str, from: (u32, &EventEdition), to: (u32, &EventEdition)) -> Self {
+        let mut old = categories_by_uid(from.1);
+        let new = categories_by_uid(to.1);
+        let mut diff = Self {
+            event: event.to_owned(),
+            from: from.0,
+            to: to.0,
+            added: Vec::new(),
+            removed: Vec::new(),
+            moved: Vec::new(),
+        };
+        for (map_uid, categories) in new {
+            let map_uid = map_uid.to_owned();
+            match old.remove(map_uid.as_str()) {
+                None => diff.added.push(Change {
+                    map_uid,
+                    categories,
+                }),
+                Some(previous) if previous != categories => diff.moved.push(Move {
+                    map_uid,
+                    from: previous,
+                    to: categories,
+                }),
+                Some(_) => (),
+            }
+        }
+        diff.removed = old
+            .into_iter()
+            .map(|(map_uid, categories)| Change {
+                map_uid: map_uid.to_owned(),
+                categories,
+            })
+            .collect();
+        diff
+    }
+
+    fn print_text(&self) {
+        let join = |categories: &BTreeSet<String>| {
+            categories.iter().cloned().collect::<Vec<_>>().join(", ")
+        };
+        println!(
+            "Edition {} -> {} of `{}`: {} added, {} removed, {} moved map(s)",
+            self.from,
+            self.to,
+            self.event,
+            self.added.len(),
+            self.removed.len(),
+            self.moved.len()
+        );
+        for map in &self.added {
+            println!("  + {} ({})", map.map_uid, join(&map.categories));
+        }
+        for map in &self.removed {
+            println!("  - {} ({})", map.map_uid, join(&map.categories));
+        }
+        for map in &self.moved {
+            println!(
+                "  ~ {} ({} -> {})",
+                map.map_uid,
+                join(&map.from),
+                join(&map.to)
+            );
+        }
+    }
+}
+
+pub async fn run(
+    ctx: &Context,
+    event_handle: &str,
+    from: u32,
+    to: u32,
+    json: bool,
+) -> anyhow::Result<()> {
+    let get = |edition| {
+        imp::get_event_edition(
+            &ctx.api_client,
+            &ctx.retry,
+            &ctx.bandwidth,
+            ctx.api_host(),
+            event_handle,
+            edition,
+            None,
+        )
+    };
+    let (old, new) = futures::try_join!(get(from), get(to))?;
+    let diff = Diff::new(event_handle, (from, &old), (to, &new));
+
+    if json {
+        let json = serde_json::to_string(&diff).context("Unable to serialize the differences")?;
+        println!("{json}");
+    } else {
+        diff.print_text();
+    }
+    Ok(())
+}
@@ -0,0 +1,159 @@
+/// Splits the parameters of a header value on `;`, outside of the quoted strings.
+fn params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    params.push(&value[start..]);
+    params
+}
+
+/// Returns the content of a quoted string, or the token as is.
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .map(|v| v.strip_suffix('"').unwrap_or(v))
+    else {
+        return value.to_owned();
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Decodes an RFC 5987 extended value, `<charset>'<language>'<percent-encoded value>`.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = parts.next()?.as_bytes();
+
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' {
+            let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+/// Keeps only the last component of a file name, without the characters invalid on Windows.
+fn sanitize(name: &str) -> Option<String> {
+    let name = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        .collect::<String>();
+    let name = name.trim().trim_end_matches('.');
+    (!name.is_empty() && name != "." && name != "..").then(|| name.to_owned())
+}
+
+/// Returns the file name of a `Content-Disposition` header, sanitized to be written as is.
+///
+/// The `filename*` parameter is preferred over `filename`, as it can hold any character.
+pub fn file_name(header: &str) -> Option<String> {
+    let mut name = None;
+    let mut ext_name = None;
+    for param in params(header).into_iter().skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename" => name = Some(unquote(value)),
+            "filename*" => ext_name = decode_ext_value(&unquote(value)),
+            _ => (),
+        }
+    }
+    ext_name.or(name).as_deref().and_then(sanitize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_file_name() {
+        assert_eq!(
+            file_name(r#"attachment; filename="a \"b\"; c.Map.Gbx""#).as_deref(),
+            Some("a b; c.Map.Gbx")
+        );
+        assert_eq!(
+            file_name("attachment; filename=Run.Map.Gbx").as_deref(),
+            Some("Run.Map.Gbx")
+        );
+        assert_eq!(file_name("attachment"), None);
+    }
+
+    #[test]
+    fn extended_file_name() {
+        assert_eq!(
+            file_name("attachment; filename*=utf-8''Caf%c3%a9%20Run.Map.Gbx").as_deref(),
+            Some("Café Run.Map.Gbx")
+        );
+        assert_eq!(
+            file_name("attachment; filename*=iso-8859-1'fr'Caf%e9.Map.Gbx").as_deref(),
+            Some("Café.Map.Gbx")
+        );
+        // Preferred over `filename`, which is kept when it can't be decoded
+        assert_eq!(
+            file_name("attachment; filename=\"Old.Map.Gbx\"; filename*=UTF-8''New%20Run.Map.Gbx")
+                .as_deref(),
+            Some("New Run.Map.Gbx")
+        );
+        assert_eq!(
+            file_name("attachment; filename=\"Old.Map.Gbx\"; filename*=koi8-r''%c1.Map.Gbx")
+                .as_deref(),
+            Some("Old.Map.Gbx")
+        );
+        assert_eq!(file_name("attachment; filename*=utf-8''%ff.Map.Gbx"), None);
+    }
+
+    #[test]
+    fn sanitized_file_name() {
+        assert_eq!(
+            file_name(r#"attachment; filename="../evil\\x;y.Map.Gbx""#).as_deref(),
+            Some("x;y.Map.Gbx")
+        );
+        assert_eq!(
+            file_name("attachment; filename*=utf-8''..%2F..%2Fetc%2Fpasswd").as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(
+            sanitize("a<b>:c|d?e*\u{7}.Map.Gbx. ").as_deref(),
+            Some("abcde.Map.Gbx")
+        );
+        assert_eq!(sanitize("dir/.."), None);
+        assert_eq!(sanitize("dir/"), None);
+    }
+}
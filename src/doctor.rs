@@ -0,0 +1,104 @@
+use std::time::Instant;
+
+use clap::ValueEnum as _;
+
+use crate::{curl, Context, MX_HOST, USER_AGENT};
+
+const PROXY_VARS: &[&str] = &[
+    "HTTP_PROXY",
+    "http_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+    "NO_PROXY",
+    "no_proxy",
+];
+
+async fn probe(name: &str, request: reqwest::RequestBuilder) -> bool {
+    let start = Instant::now();
+    match curl::send(request.header("User-Agent", USER_AGENT)).await {
+        Ok(res) => {
+            // Any HTTP response means the host is reachable, even an error status
+            println!(
+                "  [ok]   {name}: HTTP {} in {:?}",
+                res.status(),
+                start.elapsed()
+            );
+            true
+        }
+        Err(e) => {
+            println!("  [fail] {name}: {:#}", anyhow::Error::from(e));
+            false
+        }
+    }
+}
+
+pub async fn run(ctx: &Context) -> anyhow::Result<()> {
+    let api_host = ctx.api_host();
+
+    println!("Configuration:");
+    println!("  API base URL: {api_host}");
+    println!("  MX base URL:  {MX_HOST}");
+    if let Some(fallback) = &ctx.args.mx_fallback {
+        println!("  MX fallback:  {fallback}");
+    }
+    println!("  Concurrency:  {} requests", ctx.args.concurrency);
+    println!(
+        "  Retries:      {} (backoff {:?} to {:?}, {})",
+        ctx.args.max_retries,
+        ctx.retry.base_delay,
+        ctx.retry.max_delay,
+        if ctx.retry.jitter {
+            "jittered"
+        } else {
+            "no jitter"
+        },
+    );
+    if let Some(statuses) = &ctx.retry.statuses {
+        let statuses = statuses
+            .iter()
+            .map(|status| status.as_str())
+            .collect::<Vec<_>>();
+        println!("  Retried:      {}", statuses.join(", "));
+    }
+    println!("  MX min delay: {:?}", ctx.args.min_delay);
+    if let Some(user) = &ctx.args.mx_user {
+        println!("  Mirror auth:  basic, as `{user}`");
+    }
+    println!("  Timeout:      none");
+    if ctx.args.no_verify_tls {
+        println!("  TLS:          certificates NOT verified");
+    } else if let Some(path) = &ctx.args.ca_cert {
+        println!("  TLS:          also trusting `{}`", path.display());
+    }
+    if let Some(ip_version) = ctx.args.ip_version.to_possible_value() {
+        println!("  IP version:   {}", ip_version.get_name());
+    }
+    println!(
+        "  Idle pool:    {} connection(s) per host for {:?}",
+        ctx.args
+            .pool_max_idle
+            .map_or_else(|| "unbounded".to_owned(), |max| max.to_string()),
+        ctx.args.pool_idle_timeout
+    );
+
+    let proxies = PROXY_VARS
+        .iter()
+        .filter_map(|var| std::env::var(var).ok().map(|val| (var, val)))
+        .collect::<Vec<_>>();
+    if proxies.is_empty() {
+        println!("  Proxy:        none");
+    } else {
+        for (var, val) in proxies {
+            println!("  Proxy:        {var}={}", curl::redacted_proxy(&val));
+        }
+    }
+
+    println!("Checks:");
+    let api_ok = probe("API host", ctx.api_client.get(api_host)).await;
+    let mx_ok = probe("MX host", ctx.client.head(MX_HOST)).await;
+
+    anyhow::ensure!(api_ok && mx_ok, "Some checks failed");
+    Ok(())
+}
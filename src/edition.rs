@@ -0,0 +1,677 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context as _;
+use futures::StreamExt as _;
+
+use crate::{archive, cache, error, hooks, log, manifest, output, perms, progress, prune, stats};
+use crate::{
+    archive_map, complete_edition, create_dir, download_category, edition_dirs, edition_plan,
+    head_check, is_intact, manifest_entry, map_rel_path, planned_maps, sha256_of,
+    stop_if_disk_full, sums_line, write_map, Category, Command, Context, DryRun, EventEdition,
+    Layout, QueuedMap,
+};
+
+/// The maps of an edition written or skipped so far, for its manifest, checksums and index.
+#[derive(Default)]
+struct EditionFiles {
+    /// The lines of `SHA256SUMS`
+    sums: Vec<String>,
+    /// The paths of the maps relative to the edition directory, kept by `--prune`
+    written: HashSet<PathBuf>,
+    entries: Vec<manifest::Entry>,
+    stats: stats::Stats,
+    /// The stats of each output root
+    roots: Vec<stats::Stats>,
+}
+
+impl EditionFiles {
+    /// Records the map of `entry` already in the output as `rel_path`.
+    fn skip(
+        &mut self,
+        ctx: &Context,
+        rel_path: PathBuf,
+        entry: manifest::Entry,
+        reason: stats::SkipReason,
+    ) {
+        ctx.skips.add(&entry.category, &entry.map_uid, reason);
+        self.add(&ctx.args, rel_path, entry);
+        self.stats.skipped += 1;
+    }
+
+    /// Records the map of `entry` written as `rel_path`.
+    fn add(&mut self, args: &Command, rel_path: PathBuf, entry: manifest::Entry) {
+        if let Some(sha256) = entry.sha256.as_ref().filter(|_| args.checksums) {
+            self.sums.push(sums_line(sha256, &rel_path));
+        }
+        if args.manifest || args.csv.is_some() {
+            self.entries.push(entry);
+        }
+        self.written.insert(rel_path);
+    }
+}
+
+/// An edition downloaded to its directory in each output root.
+struct EditionJob<'a> {
+    ctx: &'a Context,
+    handle: &'a str,
+    edition: u32,
+    name: String,
+    dirs: Vec<PathBuf>,
+    /// The position of each map in the edition
+    order: HashMap<(String, String), (usize, usize)>,
+}
+
+impl<'a> EditionJob<'a> {
+    fn new(ctx: &'a Context, handle: &'a str, edition: u32, event: &EventEdition) -> Self {
+        // The maps finish downloading in any order, the manifest lists them in the order of the
+        // edition instead so that it doesn't change from a run to another
+        let order = event
+            .categories
+            .iter()
+            .enumerate()
+            .flat_map(|(i, cat)| {
+                cat.maps
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, map)| ((cat.handle.clone(), map.map_uid.clone()), (i, j)))
+            })
+            .collect();
+        Self {
+            ctx,
+            handle,
+            edition,
+            name: event.name.clone(),
+            dirs: edition_dirs(&ctx.args, handle, edition),
+            order,
+        }
+    }
+
+    fn write_manifest(
+        &self,
+        entries: &mut [manifest::Entry],
+        complete: bool,
+    ) -> anyhow::Result<()> {
+        let args = &self.ctx.args;
+        entries.sort_by_cached_key(|entry| {
+            let (i, j) = self
+                .order
+                .get(&(entry.category.clone(), entry.map_uid.clone()))
+                .copied()
+                .unwrap_or((usize::MAX, usize::MAX));
+            // Whatever the order of the API
+            let uid = args.reproducible.then(|| entry.map_uid.clone());
+            (i, uid, j)
+        });
+        if !args.manifest {
+            return Ok(());
+        }
+        // A filtered run doesn't download the whole edition
+        let filtered = args.limit.is_some()
+            || args.limit_categories.is_some()
+            || !args.include_maps.is_empty()
+            || !args.include_map_globs.is_empty();
+        for dir in &self.dirs {
+            create_dir(dir, args.dir_mode)?;
+            let path = manifest::Manifest::write(
+                dir,
+                self.handle,
+                self.edition,
+                &self.name,
+                complete && !filtered,
+                entries,
+            )?;
+            perms::set_mode(&path, args.file_mode)?;
+        }
+        Ok(())
+    }
+
+    /// Skips the maps listed by the manifest `path` of a previous run.
+    fn skip_resumed(
+        &self,
+        path: &Path,
+        event: &mut EventEdition,
+        files: &mut EditionFiles,
+    ) -> anyhow::Result<()> {
+        let prev = manifest::Manifest::read(path)?;
+        if prev.event != self.handle || prev.edition != self.edition {
+            log::warn!(
+                "Ignoring `{}`, it describes edition {} of `{}`",
+                path.display(),
+                prev.edition,
+                prev.event
+            );
+            return Ok(());
+        }
+        let mut done = prev
+            .maps
+            .into_iter()
+            .map(|entry| ((entry.category.clone(), entry.map_uid.clone()), entry))
+            .collect::<HashMap<_, _>>();
+        for cat in &mut event.categories {
+            let handle = &cat.handle;
+            cat.maps.retain(|map| {
+                let Some(entry) = done.remove(&(handle.clone(), map.map_uid.clone())) else {
+                    return true;
+                };
+                files.skip(
+                    self.ctx,
+                    entry.file.clone(),
+                    entry,
+                    stats::SkipReason::AlreadyPresent,
+                );
+                false
+            });
+        }
+        event.categories.retain(|cat| !cat.maps.is_empty());
+        log::info!(
+            "Skipping {} map(s) already downloaded according to `{}`",
+            files.stats.skipped,
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Skips the maps already in an output root, copying them to the other roots.
+    async fn skip_existing(
+        &self,
+        event: &mut EventEdition,
+        files: &mut EditionFiles,
+    ) -> anyhow::Result<()> {
+        let (ctx, args) = (self.ctx, &self.ctx.args);
+        let resumed = files.stats.skipped;
+        // With `--resume`, the existing files are checked against the previous manifest
+        let mut recorded = HashMap::new();
+        let manifest_path = self.dirs[0].join(manifest::FILE_NAME);
+        if args.resume && manifest_path.is_file() {
+            for entry in manifest::Manifest::read(&manifest_path)?.maps {
+                recorded.insert(entry.file.clone(), entry);
+            }
+        }
+        for cat in &mut event.categories {
+            let mut missing = Vec::with_capacity(cat.maps.len());
+            for map in std::mem::take(&mut cat.maps) {
+                let rel_path = map_rel_path(args, &cat.handle, &map);
+                let entry = recorded
+                    .get(&rel_path)
+                    .filter(|entry| entry.map_uid == map.map_uid);
+                let mut intact = Vec::with_capacity(self.dirs.len());
+                for dir in &self.dirs {
+                    intact.push(is_intact(&dir.join(&rel_path), args.if_newer_than, entry).await?);
+                }
+                let Some(existing) = self
+                    .dirs
+                    .iter()
+                    .zip(&intact)
+                    .find(|(_, intact)| **intact)
+                    .map(|(dir, _)| dir.join(&rel_path))
+                else {
+                    missing.push(map);
+                    continue;
+                };
+                // Copied from another root rather than downloaded again
+                let content = bytes::Bytes::from(
+                    std::fs::read(&existing)
+                        .with_context(|| format!("Unable to read `{}`", existing.display()))?,
+                );
+                for ((dir, root), intact) in self.dirs.iter().zip(&mut files.roots).zip(&intact) {
+                    let path = dir.join(&rel_path);
+                    if *intact {
+                        root.skipped += 1;
+                        continue;
+                    }
+                    if let Some(parent) = path.parent() {
+                        create_dir(parent, args.dir_mode)?;
+                    }
+                    output::write_file_atomic(&path, &content, args.tempdir.as_deref())
+                        .map_err(|e| stop_if_disk_full(ctx, e, files.stats.maps))?;
+                    perms::set_mode(&path, args.file_mode)?;
+                    root.add_map(content.len());
+                }
+                let sha256 = if args.checksums {
+                    Some(sha256_of(content.clone()).await?)
+                } else {
+                    None
+                };
+                let entry = manifest_entry(
+                    &cat.handle,
+                    &map,
+                    rel_path.clone(),
+                    content.len() as _,
+                    sha256,
+                );
+                files.skip(ctx, rel_path, entry, stats::SkipReason::AlreadyPresent);
+            }
+            cat.maps = missing;
+        }
+        event.categories.retain(|cat| !cat.maps.is_empty());
+        log::info!(
+            "Skipping {} map(s) already downloaded",
+            files.stats.skipped - resumed
+        );
+        Ok(())
+    }
+
+    /// Reopens the archive of an interrupted run, skipping the maps already in it.
+    async fn resume_archive(
+        &self,
+        event: &mut EventEdition,
+        files: &mut EditionFiles,
+        taken: &mut HashMap<PathBuf, String>,
+    ) -> anyhow::Result<archive::Writer> {
+        let (ctx, args) = (self.ctx, &self.ctx.args);
+        let (zip, archived) = archive::Writer::resume(
+            archive::path(&self.dirs[0]),
+            args.parallel_writes as _,
+            args.checksums,
+        )
+        .await?;
+        let resumed = files.stats.skipped;
+        for cat in &mut event.categories {
+            cat.maps.retain(|map| {
+                let rel_path = map_rel_path(args, &cat.handle, map);
+                let Some(existing) = archived.get(&archive::entry_name(&rel_path)) else {
+                    return true;
+                };
+                let entry = manifest_entry(
+                    &cat.handle,
+                    map,
+                    rel_path.clone(),
+                    existing.size,
+                    existing.sha256.clone(),
+                );
+                files.skip(ctx, rel_path, entry, stats::SkipReason::AlreadyPresent);
+                false
+            });
+        }
+        event.categories.retain(|cat| !cat.maps.is_empty());
+        log::info!(
+            "Skipping {} map(s) already in the archive",
+            files.stats.skipped - resumed
+        );
+        // Named after their UID if MX sends the name of a file already in the archive
+        for name in archived.into_keys() {
+            taken.entry(PathBuf::from(name)).or_default();
+        }
+        Ok(zip)
+    }
+
+    /// Returns the maps of the previous manifest whose file is still there, to send their ETag.
+    fn previous(
+        &self,
+        event: &EventEdition,
+    ) -> anyhow::Result<HashMap<(String, String), manifest::Entry>> {
+        let mut previous = HashMap::new();
+        let path = self.dirs[0].join(manifest::FILE_NAME);
+        if !path.is_file() {
+            return Ok(previous);
+        }
+        for entry in manifest::Manifest::read(&path)?.maps {
+            let rel_path = event
+                .categories
+                .iter()
+                .filter(|cat| cat.handle == entry.category)
+                .flat_map(|cat| &cat.maps)
+                .find(|map| map.map_uid == entry.map_uid)
+                .map(|map| map_rel_path(&self.ctx.args, &entry.category, map));
+            if rel_path.is_some_and(|p| p == entry.file && self.dirs[0].join(&p).is_file()) {
+                previous.insert((entry.category.clone(), entry.map_uid.clone()), entry);
+            }
+        }
+        Ok(previous)
+    }
+
+    /// Downloads the maps of `categories` and writes them, and returns the number of maps
+    /// which failed to download.
+    async fn download(
+        &self,
+        mut categories: Vec<Category>,
+        zip: Option<&archive::Writer>,
+        taken: HashMap<PathBuf, String>,
+        previous: &HashMap<(String, String), manifest::Entry>,
+        files: &mut EditionFiles,
+    ) -> anyhow::Result<usize> {
+        let (ctx, args) = (self.ctx, &self.ctx.args);
+        // The maps left to write in each category and their number, to know when it's complete
+        let mut left = categories
+            .iter()
+            .map(|cat| (cat.handle.clone(), (cat.maps.len(), cat.maps.len())))
+            .collect::<HashMap<_, _>>();
+
+        // The downloaded maps wait in a bounded queue, so that the downloads pause instead of
+        // piling up in memory when the writes fall behind
+        let queue = tokio::sync::Semaphore::new(args.write_queue as _);
+        let (tx, rx) = futures::channel::mpsc::channel(args.write_queue as usize - 1);
+        // The maps wait for a request slot in the order they're started, the largest categories
+        // start first so that they don't finish long after the others
+        categories.sort_by_key(|cat| std::cmp::Reverse(cat.maps.len()));
+        let downloads = async {
+            futures::stream::select_all(
+                categories
+                    .into_iter()
+                    .map(|cat| download_category(ctx, cat, previous, &queue).boxed()),
+            )
+            .map(Ok)
+            .forward(tx)
+            .await
+            .context("The writes of the maps stopped")
+        };
+
+        let taken = Mutex::new(taken);
+        let writes = async {
+            let mut failed = 0;
+            let mut writes = rx
+                .map(|res: anyhow::Result<QueuedMap>| async {
+                    // The other maps are still downloaded, the edition fails once they're written
+                    let (cat_handle, mut dl, slot) = match res {
+                        Ok(downloaded) => downloaded,
+                        Err(e) => {
+                            log::error!("{e:#}");
+                            ctx.failures.record(&e);
+                            return anyhow::Ok(None);
+                        }
+                    };
+                    if dl.map.mx_file_name.is_some() {
+                        let owner = taken
+                            .lock()
+                            .unwrap()
+                            .entry(map_rel_path(args, &cat_handle, &dl.map))
+                            .or_insert_with(|| dl.map.map_uid.clone())
+                            .clone();
+                        if owner != dl.map.map_uid {
+                            log::warn!(
+                                "MX sent the file name of another file for {}, naming it after \
+                                 its UID",
+                                dl.map
+                            );
+                            dl.map.mx_file_name = None;
+                        }
+                    }
+                    let rel_path = map_rel_path(args, &cat_handle, &dl.map);
+                    let paths = self
+                        .dirs
+                        .iter()
+                        .map(|dir| dir.join(&rel_path))
+                        .collect::<Vec<_>>();
+                    let dl = match zip {
+                        _ if dl.unchanged.is_some() => dl,
+                        Some(zip) => {
+                            archive_map(zip, &args.map_ext, &dl, &rel_path).await?;
+                            dl
+                        }
+                        None => {
+                            if args.no_create_empty_dirs {
+                                for parent in paths.iter().filter_map(|path| path.parent()) {
+                                    create_dir(parent, args.dir_mode)?;
+                                }
+                            }
+                            write_map(ctx, dl, paths.clone()).await?
+                        }
+                    };
+                    anyhow::Ok(Some((cat_handle, dl, rel_path, paths, slot)))
+                })
+                .buffered(args.parallel_writes as _);
+            while let Some(res) = writes.next().await {
+                let done = res.map_err(|e| stop_if_disk_full(ctx, e, files.stats.maps))?;
+                let Some((cat_handle, dl, rel_path, paths, _slot)) = done else {
+                    failed += 1;
+                    continue;
+                };
+                if let Some(entry) = dl.unchanged {
+                    files.skip(ctx, rel_path, entry, stats::SkipReason::Unchanged);
+                } else {
+                    for root in &mut files.roots {
+                        root.add_map(dl.content.len());
+                    }
+                    files.stats.add_map(dl.content.len());
+                    ctx.report(progress::Event::MapFinished {
+                        category: cat_handle.clone(),
+                        map_uid: dl.map.map_uid.clone(),
+                        size: dl.content.len() as _,
+                    });
+                    hooks::on_map(
+                        ctx,
+                        self.handle,
+                        self.edition,
+                        &cat_handle,
+                        &dl.map.map_uid,
+                        &paths[0],
+                    )
+                    .await?;
+                    let entry = manifest::Entry {
+                        etag: dl.etag,
+                        provenance: dl.provenance.filter(|_| args.provenance),
+                        ..manifest_entry(
+                            &cat_handle,
+                            &dl.map,
+                            rel_path.clone(),
+                            dl.content.len() as _,
+                            dl.sha256,
+                        )
+                    };
+                    files.add(args, rel_path, entry);
+                }
+
+                let Some((left, maps)) = left.get_mut(&cat_handle) else {
+                    continue;
+                };
+                *left -= 1;
+                if *left == 0 {
+                    log::info!("Wrote the maps of category `{cat_handle}`");
+                    // Rewritten after each category, so that an interrupted run leaves a partial
+                    // manifest that can be resumed with `--resume-manifest`
+                    self.write_manifest(&mut files.entries, false)?;
+                    ctx.report(progress::Event::CategoryCompleted {
+                        category: cat_handle,
+                        maps: *maps,
+                    });
+                }
+            }
+            anyhow::Ok(failed)
+        };
+        let ((), failed) = futures::try_join!(downloads, writes)?;
+        Ok(failed)
+    }
+
+    /// Writes the files describing the edition once all its maps are, and returns its stats.
+    async fn finish(
+        &self,
+        zip: Option<archive::Writer>,
+        mut files: EditionFiles,
+        expected: usize,
+    ) -> anyhow::Result<stats::Stats> {
+        let (ctx, args) = (self.ctx, &self.ctx.args);
+        if let Some(zip) = zip {
+            let path = zip.finish().await?;
+            log::info!("Wrote archive `{}`", path.display());
+        }
+
+        // Same format as `sha256sum`, so the file can be checked with `sha256sum -c`
+        files.sums.sort_by(|a, b| a[64..].cmp(&b[64..]));
+        for dir in &self.dirs {
+            if args.prune {
+                let removed = prune::run(dir, &args.map_ext, &files.written)?;
+                log::info!("Pruned {removed} stale map(s) in `{}`", dir.display());
+            }
+
+            if args.checksums {
+                create_dir(dir, args.dir_mode)?;
+                let sums_path = dir.join("SHA256SUMS");
+                output::write_file(&sums_path, files.sums.concat())?;
+                perms::set_mode(&sums_path, args.file_mode)?;
+            }
+        }
+
+        self.write_manifest(&mut files.entries, true)?;
+        if args.csv.is_some() {
+            let edition_dir = &self.dirs[0];
+            let rel_dir = edition_dir.strip_prefix(&args.out).unwrap_or(edition_dir);
+            ctx.index.add(rel_dir, &files.entries);
+        }
+
+        if self.dirs.len() > 1 {
+            for (dir, root) in self.dirs.iter().zip(&files.roots) {
+                log::info!(
+                    "`{}`: {} map(s) written, {} skipped",
+                    dir.display(),
+                    root.maps,
+                    root.skipped
+                );
+            }
+        }
+
+        let stats = files.stats;
+        let accounted = stats.maps + stats.skipped;
+        if accounted != expected {
+            let msg = format!(
+                "{accounted} map(s) were written or skipped instead of the {expected} of edition \
+                 {} of `{}`",
+                self.edition, self.handle
+            );
+            anyhow::ensure!(!args.strict, msg);
+            log::warn!("{msg}");
+        }
+
+        hooks::on_complete(ctx, self.handle, self.edition, &self.dirs[0]).await?;
+
+        Ok(stats)
+    }
+}
+
+/// Downloads the maps of an edition, and returns its stats.
+pub async fn download(
+    ctx: &Context,
+    event_handle: &str,
+    event_edition: u32,
+) -> anyhow::Result<stats::Stats> {
+    let args = &ctx.args;
+    if args.skip_complete {
+        if let Some(maps) = complete_edition(args, event_handle, event_edition) {
+            log::info!(
+                "Skipping edition {event_edition} of `{event_handle}`, its {} map(s) are \
+                 already downloaded according to its manifest",
+                maps.len()
+            );
+            for entry in &maps {
+                ctx.skips.add(
+                    &entry.category,
+                    &entry.map_uid,
+                    stats::SkipReason::AlreadyPresent,
+                );
+            }
+            return Ok(stats::Stats {
+                skipped: maps.len(),
+                ..Default::default()
+            });
+        }
+    }
+    let cache = args
+        .prefer_cached_on_error
+        .then(|| cache::edition_path(&args.out, event_handle, event_edition));
+    let (mut event, filtered_out) =
+        planned_maps(ctx, event_handle, event_edition, cache.as_deref()).await?;
+    // The maps already downloaded are only skipped later, an edition up to date isn't empty
+    anyhow::ensure!(
+        args.allow_empty
+            || args.dry_run.is_some()
+            || event.categories.iter().any(|cat| !cat.maps.is_empty()),
+        "Edition {event_edition} of `{event_handle}` has no map to download, check the event \
+         handle and the filters, or use `--allow-empty`"
+    );
+
+    let job = EditionJob::new(ctx, event_handle, event_edition, &event);
+    for (cat_handle, map_uid) in filtered_out {
+        ctx.skips
+            .add(&cat_handle, &map_uid, stats::SkipReason::FilteredOut);
+    }
+
+    let mut files = EditionFiles::default();
+    if let Some(path) = &args.resume_manifest {
+        job.skip_resumed(path, &mut event, &mut files)?;
+    }
+
+    // The number of maps each written or skipped once the edition is downloaded
+    let mut expected = job.order.len();
+    if args.head_check {
+        let maps = |event: &EventEdition| event.categories.iter().map(|cat| cat.maps.len()).sum();
+        let before: usize = maps(&event);
+        head_check(ctx, &mut event).await?;
+        expected -= before - maps(&event);
+    }
+
+    if let Some(dry_run) = args.dry_run {
+        let plan = edition_plan(args, event_handle, event_edition, &event, &job.dirs[0])?;
+        match dry_run {
+            DryRun::Text => plan.print_text(),
+            DryRun::Json => plan.print_json()?,
+        }
+        return Ok(files.stats);
+    }
+
+    for dir in &job.dirs {
+        if let Some(handle_dir) = dir.parent() {
+            create_dir(handle_dir, args.dir_mode)?;
+        }
+        // Otherwise created with the first file written in it
+        if !args.zip && !args.no_create_empty_dirs {
+            create_dir(dir, args.dir_mode)?;
+        }
+    }
+    files.roots = vec![stats::Stats::default(); job.dirs.len()];
+
+    if args.skip_existing || (args.resume && !args.zip) || args.if_newer_than.is_some() {
+        job.skip_existing(&mut event, &mut files).await?;
+    }
+
+    let previous = if args.conditional {
+        job.previous(&event)?
+    } else {
+        HashMap::new()
+    };
+
+    log::info!("Downloading content from MX...");
+
+    // The paths planned for the maps named after their UID and the files written next to them,
+    // with the UID of the map they belong to, so that MX can't send the name of another file
+    let mut taken = HashMap::new();
+    for cat in &event.categories {
+        for map in &cat.maps {
+            // Not downloaded yet, so without the name sent by MX
+            taken.insert(map_rel_path(args, &cat.handle, map), map.map_uid.clone());
+        }
+    }
+    for name in [manifest::FILE_NAME, "SHA256SUMS"] {
+        taken.insert(PathBuf::from(name), String::new());
+    }
+    let zip = if args.zip && args.resume {
+        Some(
+            job.resume_archive(&mut event, &mut files, &mut taken)
+                .await?,
+        )
+    } else if args.zip {
+        Some(archive::Writer::create(
+            archive::path(&job.dirs[0]),
+            args.parallel_writes as _,
+        )?)
+    } else {
+        None
+    };
+    if let (Layout::Nested, None, false) = (args.layout, &zip, args.no_create_empty_dirs) {
+        for cat in &event.categories {
+            for dir in &job.dirs {
+                create_dir(&dir.join(&cat.handle), args.dir_mode)?;
+            }
+        }
+    }
+
+    let failed = job
+        .download(event.categories, zip.as_ref(), taken, &previous, &mut files)
+        .await?;
+    if failed > 0 {
+        job.write_manifest(&mut files.entries, false)?;
+        return Err(error::MapsFailed(failed).into());
+    }
+
+    job.finish(zip, files, expected).await
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::{fmt, io};
+
+use crate::output;
+
+/// The class of a fatal error, so that wrappers can decide whether to retry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The host couldn't be reached, didn't answer in time or the response was interrupted
+    Network,
+    /// The server answered `404 Not Found`
+    NotFound,
+    /// The server answered with another failed status
+    Http,
+    /// The response couldn't be parsed
+    Parse,
+    /// The downloaded map isn't the expected one
+    Validation,
+    /// The output filesystem is out of space
+    DiskFull,
+    /// Reading or writing a local file failed
+    Io,
+    Other,
+}
+
+impl ErrorKind {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Network => "network",
+            Self::NotFound => "not found",
+            Self::Http => "HTTP",
+            Self::Parse => "parse",
+            Self::Validation => "validation",
+            Self::DiskFull => "disk full",
+            Self::Io => "I/O",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// A downloaded map which doesn't match what the API or MX announced.
+#[derive(Debug)]
+pub struct Invalid(pub String);
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Invalid {}
+
+/// Some maps of an edition failed to download, each of them already recorded in the failures.
+#[derive(Debug)]
+pub struct MapsFailed(pub usize);
+
+impl fmt::Display for MapsFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} map(s) failed to download", self.0)
+    }
+}
+
+impl std::error::Error for MapsFailed {}
+
+/// The error of a request shared by several callers, keeping the types of its causes.
+#[derive(Debug, Clone)]
+pub struct Shared(pub Arc<anyhow::Error>);
+
+impl fmt::Display for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Shared {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Returns the causes of the error, the shared errors being replaced by their own error.
+fn causes(e: &anyhow::Error) -> impl Iterator<Item = &(dyn std::error::Error + 'static)> {
+    e.chain().map(|cause| match cause.downcast_ref::<Shared>() {
+        Some(shared) => &**shared.0,
+        None => cause,
+    })
+}
+
+/// The number of failures of each kind during the run, for the final report.
+#[derive(Default)]
+pub struct Failures(Mutex<BTreeMap<ErrorKind, usize>>);
+
+impl Failures {
+    pub fn record(&self, e: &anyhow::Error) {
+        if e.chain().any(|cause| cause.is::<MapsFailed>()) {
+            return;
+        }
+        let kind = ErrorReport::new(e).kind;
+        *self.0.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    /// Returns the failures grouped by kind, like `2 network, 1 not found`, if there were any.
+    pub fn summary(&self) -> Option<String> {
+        let failures = self.0.lock().unwrap();
+        (!failures.is_empty()).then(|| {
+            failures
+                .iter()
+                .map(|(kind, count)| format!("{count} {}", kind.label()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+    }
+}
+
+/// A fatal error, printed as JSON with `--error-format json`.
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    /// The whole chain of the error, as printed in the human format
+    pub message: String,
+    /// The URL of the failed request, if any
+    pub url: Option<String>,
+    /// The HTTP status of the failed request, if the server answered
+    pub status: Option<u16>,
+}
+
+impl ErrorReport {
+    pub fn new(e: &anyhow::Error) -> Self {
+        let mut report = Self {
+            kind: ErrorKind::Other,
+            message: format!("{e:#}"),
+            url: None,
+            status: None,
+        };
+        for cause in causes(e) {
+            if cause.is::<output::DiskFull>() {
+                report.kind = ErrorKind::DiskFull;
+            } else if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                report.url = e.url().map(ToString::to_string);
+                report.status = e.status().map(|status| status.as_u16());
+                report.kind = match e.status() {
+                    Some(reqwest::StatusCode::NOT_FOUND) => ErrorKind::NotFound,
+                    Some(_) => ErrorKind::Http,
+                    None if e.is_decode() => ErrorKind::Parse,
+                    None => ErrorKind::Network,
+                };
+            } else if cause.is::<Invalid>() {
+                report.kind = ErrorKind::Validation;
+            } else if cause.is::<serde_json::Error>() {
+                report.kind = ErrorKind::Parse;
+            } else if cause.is::<io::Error>() {
+                report.kind = ErrorKind::Io;
+            } else {
+                continue;
+            }
+            break;
+        }
+        report
+    }
+
+    /// Prints the error on a single line of stderr.
+    pub fn print(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => eprintln!("{json}"),
+            Err(_) => eprintln!("{}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::ResponseBuilderExt as _;
+
+    use super::*;
+
+    fn not_found(url: &str) -> anyhow::Error {
+        let res = http::Response::builder()
+            .status(404)
+            .url(url.parse().unwrap())
+            .body(Vec::new())
+            .unwrap();
+        reqwest::Response::from(res)
+            .error_for_status()
+            .unwrap_err()
+            .into()
+    }
+
+    #[test]
+    fn map_not_found_is_http_error() {
+        let url = "https://sm.mania.exchange/maps/download/1";
+        let e = not_found(url).context("Unable to download map");
+        let report = ErrorReport::new(&e);
+        assert_eq!(report.kind, ErrorKind::NotFound);
+        assert_eq!(report.url.as_deref(), Some(url));
+        assert_eq!(report.status, Some(404));
+    }
+
+    #[test]
+    fn failures_are_grouped_once() {
+        let failures = Failures::default();
+        let url = "https://sm.mania.exchange/maps/download/1";
+        failures.record(&not_found(url).context("Unable to download map 1"));
+        failures.record(&not_found(url).context("Unable to download map 2"));
+        failures.record(&anyhow::anyhow!("Unable to write"));
+        // Its maps are already recorded
+        failures.record(&anyhow::Error::new(MapsFailed(2)).context("Edition 1 failed"));
+        assert_eq!(failures.summary().as_deref(), Some("2 not found, 1 other"));
+    }
+
+    #[test]
+    fn shared_error_keeps_its_type() {
+        let url = "https://sm.mania.exchange/maps/download/1";
+        let shared = Shared(Arc::new(not_found(url).context("Unable to download map")));
+        let e = anyhow::Error::new(shared).context("Unable to collect maps downloads");
+        let report = ErrorReport::new(&e);
+        assert_eq!(report.kind, ErrorKind::NotFound);
+        assert_eq!(report.url.as_deref(), Some(url));
+        assert_eq!(report.status, Some(404));
+        assert_eq!(
+            report.message,
+            format!(
+                "Unable to collect maps downloads: Unable to download map: HTTP status client \
+                 error (404 Not Found) for url ({url})"
+            )
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+
+use crate::log;
+use crate::stats::{Report, Stats};
+use crate::{imp, Context};
+
+/// Downloads the editions of the event newer than `last`, oldest first.
+///
+/// Stops at the first edition which fails, so that it's downloaded again at the next check.
+async fn check(ctx: &Context, event_handle: &str, last: &mut u32) -> anyhow::Result<Stats> {
+    let mut editions = imp::get_editions_of(
+        &ctx.api_client,
+        &ctx.retry,
+        &ctx.bandwidth,
+        ctx.api_host(),
+        event_handle,
+    )
+    .await?
+    .into_iter()
+    .map(|edition| edition.id)
+    .filter(|id| id > last)
+    .collect::<Vec<_>>();
+    editions.sort_unstable();
+
+    if editions.is_empty() {
+        log::info!("No new edition of `{event_handle}`");
+    }
+    let mut stats = Stats::default();
+    for edition in editions {
+        log::info!("New edition {edition} of `{event_handle}`");
+        match crate::process_edition(ctx, event_handle, edition).await {
+            Ok(edition_stats) => {
+                log::info!("Downloaded edition {edition} of `{event_handle}`: {edition_stats}");
+                stats += edition_stats;
+                *last = edition;
+            }
+            Err(e) => {
+                ctx.failures.record(&e);
+                log::error!(
+                    "Edition {edition} of `{event_handle}` failed, retrying at the next check: \
+                     {e:#}"
+                );
+                break;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Downloads the last edition of the event, then checks for new editions every `interval`
+/// until interrupted with Ctrl-C.
+pub async fn run(ctx: &Context, interval: Duration) -> anyhow::Result<Report> {
+    let start = Instant::now();
+    crate::probe_roots(ctx)?;
+    let (event_handle, mut last) =
+        crate::resolve_edition(ctx, ctx.args.event_handle.clone(), None).await?;
+    let mut total = crate::process_edition(ctx, &event_handle, last).await?;
+    log::info!(
+        "Following `{event_handle}`, checking for new editions every {}",
+        humantime::format_duration(interval)
+    );
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let next = async {
+            tokio::time::sleep(interval).await;
+            check(ctx, &event_handle, &mut last).await
+        };
+        let res = tokio::select! {
+            res = &mut ctrl_c => {
+                res.context("Unable to listen for Ctrl-C")?;
+                log::info!("Interrupted, no longer following `{event_handle}`");
+                break;
+            }
+            res = next => res,
+        };
+        match res {
+            Ok(stats) => total += stats,
+            Err(e) => {
+                ctx.failures.record(&e);
+                log::error!("Checking `{event_handle}` for new editions failed: {e:#}");
+            }
+        }
+    }
+
+    Ok(Report::new(
+        total,
+        start.elapsed(),
+        ctx.mx_in_flight.peak(),
+        ctx.memory.peak(),
+        ctx.bandwidth.per_host(),
+        ctx.skips.list(),
+    ))
+}
@@ -0,0 +1,179 @@
+use std::io::Read as _;
+
+use anyhow::Context as _;
+
+use crate::log;
+
+const MAGIC: &[u8] = b"GBX";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const MAP_CLASS_ID: u32 = 0x0304_3000;
+/// The header chunk containing the identifier of the map.
+const MAP_INFO_CHUNK_ID: u32 = 0x0304_3003;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .context("Unexpected end of the Gbx header")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).context("Invalid string in the Gbx header")
+    }
+
+    /// Reads the first lookback string of a chunk, which must be a new string.
+    fn first_lookback_string(&mut self) -> anyhow::Result<String> {
+        let _version = self.u32()?;
+        let index = self.u32()?;
+        anyhow::ensure!(
+            index & 0xc000_0000 != 0 && index & 0x3fff_ffff == 0,
+            "Unexpected string reference in the Gbx header"
+        );
+        self.string()
+    }
+}
+
+/// Returns the UID of the map stored in the header of the Gbx file.
+pub fn map_uid(data: &[u8]) -> anyhow::Result<String> {
+    let mut r = Reader { data, pos: 0 };
+    anyhow::ensure!(r.bytes(3)? == MAGIC, "Not a Gbx file");
+    let version = r.u16()?;
+    anyhow::ensure!(version >= 6, "Unsupported Gbx version {version}");
+    // Format, compression of the reference table and of the body, unknown byte
+    r.bytes(4)?;
+    let class_id = r.u32()?;
+    anyhow::ensure!(
+        class_id == MAP_CLASS_ID,
+        "Not a map Gbx file (class ID {class_id:#010x})"
+    );
+    let _user_data_size = r.u32()?;
+    let chunks_len = r.u32()?;
+
+    let mut offset = 0;
+    let mut info = None;
+    for _ in 0..chunks_len {
+        let id = r.u32()?;
+        // The highest bit marks the "heavy" chunks
+        let size = (r.u32()? & 0x7fff_ffff) as usize;
+        if id == MAP_INFO_CHUNK_ID {
+            info = Some((offset, size));
+        }
+        offset += size;
+    }
+    let (offset, size) = info.context("The Gbx header has no map info chunk")?;
+
+    r.pos += offset;
+    let mut r = Reader {
+        data: r.bytes(size)?,
+        pos: 0,
+    };
+    let _version = r.u8()?;
+    r.first_lookback_string()
+}
+
+/// Fails if the content type of a map response is textual, like the HTML of an error page
+/// served with a 200 status. A missing or unknown type is accepted.
+pub fn check_content_type(content_type: Option<&str>) -> anyhow::Result<()> {
+    let Some(content_type) = content_type else {
+        return Ok(());
+    };
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let textual = media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json" | "application/xml" | "application/javascript"
+        );
+    anyhow::ensure!(
+        !textual,
+        crate::error::Invalid(format!(
+            "The response is `{content_type}` instead of a map, it wasn't written"
+        ))
+    );
+    Ok(())
+}
+
+/// Returns the Gbx file from the body of a map response.
+///
+/// A Gbx file is compressed internally and must be stored as is. A body sent with
+/// `Content-Encoding: gzip` is only decompressed if it's actually gzipped, some servers set the
+/// header on the raw Gbx bytes.
+pub fn decode_body(content: bytes::Bytes, encoding: Option<&str>) -> anyhow::Result<bytes::Bytes> {
+    let gzip = encoding
+        .is_some_and(|e| e.eq_ignore_ascii_case("gzip") || e.eq_ignore_ascii_case("x-gzip"));
+    if !gzip || content.starts_with(MAGIC) || !content.starts_with(GZIP_MAGIC) {
+        if gzip {
+            log::debug!("Map body marked as gzipped isn't, keeping it as is");
+        }
+        return Ok(content);
+    }
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(&content[..])
+        .read_to_end(&mut decoded)
+        .context("Unable to decompress the gzipped map")?;
+    Ok(decoded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use super::*;
+
+    const MAP: &[u8] = b"GBX\x06\x00BUCR map content";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decodes_gzip_body() {
+        let body = bytes::Bytes::from(gzip(MAP));
+        assert_eq!(decode_body(body, Some("gzip")).unwrap(), MAP);
+    }
+
+    #[test]
+    fn keeps_identity_body() {
+        let body = bytes::Bytes::from_static(MAP);
+        assert_eq!(decode_body(body.clone(), None).unwrap(), MAP);
+        assert_eq!(decode_body(body, Some("identity")).unwrap(), MAP);
+    }
+
+    #[test]
+    fn fails_on_corrupt_body() {
+        let mut body = gzip(MAP);
+        body.truncate(body.len() / 2);
+        assert!(decode_body(body.into(), Some("gzip")).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::Path;
+
+use tokio::process::Command;
+
+use crate::{log, Context};
+
+fn shell(cmd: &str) -> Command {
+    if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(cmd);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(cmd).arg("soevent");
+        c
+    }
+}
+
+async fn run(ctx: &Context, name: &str, mut cmd: Command) -> anyhow::Result<()> {
+    // stdout only has the JSON of the run then, one object per line
+    if ctx.args.events_jsonl || ctx.args.output_json {
+        cmd.stdout(std::io::stderr());
+    }
+    let res = match cmd.status().await {
+        Ok(status) if status.success() => {
+            log::debug!("Hook `{name}` succeeded");
+            return Ok(());
+        }
+        Ok(status) => anyhow::anyhow!("Hook `{name}` failed with {status}"),
+        Err(e) => anyhow::Error::new(e).context(format!("Unable to run hook `{name}`")),
+    };
+
+    if ctx.args.strict {
+        Err(res)
+    } else {
+        log::warn!("{res:#}");
+        Ok(())
+    }
+}
+
+/// Runs the `--on-map` hook with the path of the written map as first argument.
+pub async fn on_map(
+    ctx: &Context,
+    event_handle: &str,
+    event_edition: u32,
+    category: &str,
+    map_uid: &str,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let Some(hook) = &ctx.args.on_map else {
+        return Ok(());
+    };
+    let mut cmd = shell(hook);
+    cmd.arg(path)
+        .env("SOEVENT_EVENT", event_handle)
+        .env("SOEVENT_EDITION", event_edition.to_string())
+        .env("SOEVENT_CATEGORY", category)
+        .env("SOEVENT_MAP_UID", map_uid)
+        .env("SOEVENT_MAP_PATH", path);
+    run(ctx, "on-map", cmd).await
+}
+
+/// Runs the `--on-complete` hook with the path of the edition directory as first argument.
+pub async fn on_complete(
+    ctx: &Context,
+    event_handle: &str,
+    event_edition: u32,
+    edition_dir: &Path,
+) -> anyhow::Result<()> {
+    let Some(hook) = &ctx.args.on_complete else {
+        return Ok(());
+    };
+    let mut cmd = shell(hook);
+    cmd.arg(edition_dir)
+        .env("SOEVENT_EVENT", event_handle)
+        .env("SOEVENT_EDITION", event_edition.to_string())
+        .env("SOEVENT_EDITION_DIR", edition_dir);
+    run(ctx, "on-complete", cmd).await
+}
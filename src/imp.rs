@@ -1,46 +1,183 @@
+use std::collections::HashSet;
+use std::path::Path;
+
 use anyhow::Context;
 
-use crate::{EventEdition, SimpleEventEdition};
+use crate::{cache, curl, log, retry, stats, EventEdition, SimpleEventEdition};
+
+const SNIPPET_RADIUS: usize = 40;
+
+/// Returns the part of `body` around the given 1-based line and column.
+fn snippet(body: &str, line: usize, column: usize) -> String {
+    let line = body.lines().nth(line.saturating_sub(1)).unwrap_or_default();
+    let chars = line.chars().collect::<Vec<_>>();
+    let start = column.saturating_sub(SNIPPET_RADIUS + 1).min(chars.len());
+    let end = (column + SNIPPET_RADIUS).min(chars.len());
+    format!(
+        "{}{}{}",
+        if start > 0 { "..." } else { "" },
+        chars[start..end].iter().collect::<String>(),
+        if end < chars.len() { "..." } else { "" },
+    )
+}
 
-#[tracing::instrument(skip(client), err, ret(Display))]
+fn parse_json<T: serde::de::DeserializeOwned>(body: &str) -> anyhow::Result<T> {
+    let de = &mut serde_json::Deserializer::from_str(body);
+    serde_path_to_error::deserialize(de).map_err(|e| {
+        let path = e.path().to_string();
+        let inner = e.into_inner();
+        let snippet = snippet(body, inner.line(), inner.column());
+        anyhow::Error::new(inner).context(format!("Invalid JSON at `{path}`, near `{snippet}`"))
+    })
+}
+
+/// Requests a JSON document, and returns its body with its parsed content.
+///
+/// A body which can't be parsed is only requested again if the policy retries parse errors.
+async fn get_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    policy: &retry::Policy,
+    bandwidth: &stats::Bandwidth,
+    url: &str,
+) -> anyhow::Result<(String, T)> {
+    retry::run(policy, || async {
+        let res = policy.check(curl::send(client.get(url)).await)?;
+        let final_url = res.url().clone();
+        let body = res.text().await.map_err(retry::Error::transient)?;
+        bandwidth.add(&final_url, body.len());
+        match parse_json(&body).context("Failed to parse JSON from response") {
+            Ok(value) => Ok((body, value)),
+            Err(e) if policy.parse_errors => Err(retry::Error::Transient(e)),
+            Err(e) => Err(retry::Error::Permanent(e)),
+        }
+    })
+    .await
+}
+
+/// Requests an event edition.
+///
+/// If a `cache` path is provided, the response is stored there, and used instead if the
+/// request fails.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(client, policy, bandwidth, cache), err, ret(Display))
+)]
 pub async fn get_event_edition(
     client: &reqwest::Client,
+    policy: &retry::Policy,
+    bandwidth: &stats::Bandwidth,
     host: &str,
     handle: &str,
     edition: u32,
+    cache: Option<&Path>,
 ) -> anyhow::Result<EventEdition> {
     let url = format!("{host}/event/{handle}/{edition}");
-    tracing::info!("Requesting event edition at {url}...");
-    client
-        .get(&url)
-        .send()
-        .await
-        .context("Failed to send request")?
-        .json()
+    log::info!("Requesting event edition at {url}...");
+    let res = get_json(client, policy, bandwidth, &url)
         .await
-        .context("Failed to parse JSON from response")
+        .context("Failed to request event edition");
+    match (res, cache) {
+        (Ok((body, event)), Some(cache)) => {
+            if let Err(e) = cache::store(cache, &body) {
+                log::warn!("Unable to cache the event edition: {e:#}");
+            }
+            Ok(event)
+        }
+        (Ok((_, event)), None) => Ok(event),
+        (Err(e), Some(cache)) if cache.is_file() => {
+            log::warn!(
+                "{e:#}, using the cached response `{}` instead",
+                cache.display()
+            );
+            parse_json(&cache::load(cache)?).context("Failed to parse the cached response")
+        }
+        (Err(e), _) => Err(e),
+    }
 }
 
-#[tracing::instrument(skip(client, event_handle), err, ret(Display))]
+/// The shapes of the editions response accepted from the API.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Editions {
+    Array(Vec<SimpleEventEdition>),
+    /// A page of the editions, followed by the page at the `next` URL if any.
+    Wrapped {
+        data: Vec<SimpleEventEdition>,
+        #[serde(default)]
+        next: Option<String>,
+    },
+    Single(SimpleEventEdition),
+}
+
+impl Editions {
+    /// Returns the editions of the page, and the URL of the next page.
+    fn into_page(self) -> (Vec<SimpleEventEdition>, Option<String>) {
+        match self {
+            Self::Array(editions) => {
+                log::debug!("Editions response is an array");
+                (editions, None)
+            }
+            Self::Wrapped { data, next } => {
+                log::debug!("Editions response is an object with a `data` array");
+                (data, next)
+            }
+            Self::Single(edition) => {
+                log::debug!("Editions response is a single edition");
+                (vec![edition], None)
+            }
+        }
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(client, policy, bandwidth, event_handle), err, ret(Display))
+)]
 pub async fn get_last_edition_of(
     client: &reqwest::Client,
+    policy: &retry::Policy,
+    bandwidth: &stats::Bandwidth,
     host: &str,
     event_handle: &str,
 ) -> anyhow::Result<SimpleEventEdition> {
-    let url = format!("{host}/event/{event_handle}");
+    get_editions_of(client, policy, bandwidth, host, event_handle)
+        .await?
+        .into_iter()
+        .max_by_key(|o| o.id)
+        .context("The event has no edition")
+}
 
-    tracing::info!("Requesting event editions at {url}...");
+pub async fn get_editions_of(
+    client: &reqwest::Client,
+    policy: &retry::Policy,
+    bandwidth: &stats::Bandwidth,
+    host: &str,
+    event_handle: &str,
+) -> anyhow::Result<Vec<SimpleEventEdition>> {
+    let mut url = format!("{host}/event/{event_handle}");
+    let mut visited = HashSet::new();
+    let mut editions = Vec::new();
+    loop {
+        log::info!("Requesting event editions at {url}...");
 
-    Ok(
-        client
-            .get(&url)
-            .send()
-            .await?
-            .json::<Vec<SimpleEventEdition>>()
+        let (_, page) = get_json::<Editions>(client, policy, bandwidth, &url)
             .await
-            .context("Unable to parse JSON response for event editions")?
-            .into_iter()
-            .max_by_key(|o| o.id)
-            .unwrap(), // The event must have at least one edition
-    )
+            .context("Failed to request event editions")?;
+        let (page, next) = page.into_page();
+        editions.extend(page);
+        visited.insert(url.clone());
+
+        let Some(next) = next else {
+            return Ok(editions);
+        };
+        // The next page may be relative to the current one
+        url = reqwest::Url::parse(&url)
+            .and_then(|base| base.join(&next))
+            .with_context(|| format!("Invalid next page `{next}` of the event editions"))?
+            .to_string();
+        anyhow::ensure!(
+            !visited.contains(&url),
+            "The pages of the event editions loop back to {url}"
+        );
+    }
 }
@@ -0,0 +1,60 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::manifest;
+
+struct Row {
+    category: String,
+    map_uid: String,
+    mx_id: crate::MxId,
+    /// Relative to the output directory
+    file: PathBuf,
+    size: u64,
+}
+
+/// The maps downloaded by the run, written as CSV with `--csv`.
+#[derive(Default)]
+pub struct Index(Mutex<Vec<Row>>);
+
+/// Quotes a CSV field if needed, doubling its quotes.
+fn field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+impl Index {
+    /// Adds the maps of an edition, whose directory is relative to the output directory.
+    pub fn add(&self, edition_dir: &Path, entries: &[manifest::Entry]) {
+        self.0
+            .lock()
+            .unwrap()
+            .extend(entries.iter().map(|entry| Row {
+                category: entry.category.clone(),
+                map_uid: entry.map_uid.clone(),
+                mx_id: entry.mx_id,
+                file: edition_dir.join(&entry.file),
+                size: entry.size,
+            }));
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut rows = self.0.lock().unwrap();
+        rows.sort_by(|a, b| a.file.cmp(&b.file));
+        let mut out = String::from("category,uid,mx_id,filename,size\n");
+        for row in rows.iter() {
+            out += &format!(
+                "{},{},{},{},{}\n",
+                field(&row.category),
+                field(&row.map_uid),
+                row.mx_id,
+                field(&row.file.to_string_lossy()),
+                row.size
+            );
+        }
+        crate::output::write_file(path, out)
+    }
+}
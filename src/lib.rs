@@ -0,0 +1,2440 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context as _;
+use futures::{StreamExt, TryStreamExt};
+use sha2::Digest as _;
+
+mod archive;
+mod batch;
+mod cache;
+mod cas;
+mod categories;
+mod compare;
+mod curl;
+mod disposition;
+mod doctor;
+mod edition;
+mod error;
+mod follow;
+mod gbx;
+mod hooks;
+mod imp;
+mod index;
+mod log;
+mod manifest;
+mod maplist;
+mod metrics;
+mod output;
+mod pacing;
+mod perms;
+mod plan;
+pub mod progress;
+mod prune;
+mod retry;
+mod script;
+mod spinner;
+mod stats;
+mod urls;
+#[cfg(feature = "localhost_test")]
+mod vcr;
+mod version;
+
+pub use error::{ErrorKind, ErrorReport};
+pub use spinner::LogWriter;
+pub use stats::{Report, Skip, SkipReason};
+
+const API_HOST: &str = "https://obstacle.titlepack.io/api";
+const MX_HOST: &str = "https://sm.mania.exchange";
+const USER_AGENT: &str = "obstacle (discord @ahmadbky)";
+
+// The command line arguments, which are also the options of the library.
+#[derive(Debug, clap::Parser)]
+#[command(about = "Downloads the maps of the events of Obstacle from ManiaExchange")]
+#[command(group(clap::ArgGroup::new("mirror").args(["map_url_template", "mx_fallback"]).multiple(true)))]
+pub struct Command {
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+
+    event_handle: Option<String>,
+    event_edition: Option<u32>,
+    /// Output directory, the `$VAR` and `${VAR}` references to environment variables are
+    /// expanded, and `%VAR%` on Windows. `$$` is a literal `$`, and `%%` a literal `%` on Windows
+    #[arg(long, short, default_value = "./", value_parser = expand_env)]
+    out: String,
+    /// Also write the maps in this output directory, can be repeated
+    #[arg(long = "mirror", value_name = "DIR")]
+    mirrors: Vec<String>,
+    /// Only create the directories of the edition and its categories when a file is written in
+    /// them, instead of before downloading
+    #[arg(long)]
+    no_create_empty_dirs: bool,
+    /// Write the maps of each edition to a `<edition>.zip` archive next to the edition
+    /// directory, instead of separate files
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "mirrors", "skip_existing", "prune", "cas_store", "conditional",
+            "resume_manifest", "on_map", "if_newer_than",
+        ]
+    )]
+    zip: bool,
+    /// Write the maps to temporary files in this directory before moving them to the output
+    /// directory. By default they're written next to their final path: when this directory is
+    /// on another filesystem, the files are copied instead of renamed, and written twice
+    #[arg(long, value_name = "DIR")]
+    tempdir: Option<PathBuf>,
+    /// Print the maps that would be downloaded, without downloading or writing anything
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "text")]
+    dry_run: Option<DryRun>,
+    /// Make the edition directory complete and up to date, without removing anything: the maps
+    /// whose file exists are kept if they match the size and the SHA-256 recorded in the
+    /// `manifest.json` of the edition (when there's one), the others are downloaded. With
+    /// `--zip`, the maps already in the archive of the edition, or in the temporary archive left
+    /// by an interrupted run, are kept
+    #[arg(long, conflicts_with = "prune")]
+    resume: bool,
+    /// Don't download the maps whose file already exists, copying them to the output
+    /// directories missing them
+    #[arg(long)]
+    skip_existing: bool,
+    /// Don't download the maps whose file was modified after this date, like `2024-05-01` or
+    /// `2024-05-01 12:00:00` in UTC. The maps without a file are always downloaded
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    if_newer_than: Option<SystemTime>,
+    /// Event handle used when none is provided
+    #[arg(long, env = "SOEVENT_DEFAULT_HANDLE", default_value = "campaign")]
+    default_handle: String,
+    /// Download the editions listed in a file, one `<handle> [edition]` per line
+    #[arg(long, conflicts_with_all = ["event_handle", "event_edition"])]
+    batch: Option<PathBuf>,
+    /// Download the maps listed in a file instead of an event, one `<map_uid> <mx_id> [category]`
+    /// per line. The maps without a category are written at the root of the output directory
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["event_handle", "event_edition", "batch"])]
+    map_list: Option<PathBuf>,
+    /// After downloading the last edition, check the event for new editions at this interval and
+    /// download them, until interrupted
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        value_parser = humantime::parse_duration,
+        conflicts_with_all = ["batch", "map_list", "event_edition"]
+    )]
+    follow: Option<Duration>,
+    /// Only download the last edition of the event if it's newer than the last edition
+    /// downloaded with this flag, recorded in the output directory
+    #[arg(long, conflicts_with_all = ["batch", "map_list", "event_edition", "follow"])]
+    refresh_latest: bool,
+    /// Download all the editions of the event whose ID is greater than this one, oldest first
+    #[arg(
+        long,
+        value_name = "ID",
+        conflicts_with_all = ["batch", "map_list", "event_edition", "follow", "refresh_latest"]
+    )]
+    since_edition: Option<u32>,
+    /// How the map files are laid out in the edition directory
+    #[arg(long, value_enum, default_value_t = Layout::Nested)]
+    layout: Layout,
+    /// Separator between the category handle and the map name with `--layout prefixed`
+    #[arg(long, default_value = "__", value_parser = parse_separator)]
+    prefix_sep: String,
+    /// How the map files are named
+    #[arg(long, value_enum, default_value_t = Naming::Uid)]
+    naming: Naming,
+    /// Extension of the map files, appended to the name chosen by `--naming`. The file names
+    /// sent by MX with `--naming mx` are kept as is
+    #[arg(long, value_name = "EXT", default_value = ".Map.Gbx", value_parser = parse_map_ext)]
+    map_ext: String,
+    /// Order of the maps in each category, applied before `--limit`. The maps are still
+    /// downloaded concurrently, but they're numbered, written and listed in the manifest in
+    /// this order
+    #[arg(long, value_enum, default_value_t = Sort::Api)]
+    sort: Sort,
+    /// Write the maps in `<out>/<handle>` rather than `<out>/<handle>/<edition>`, so the
+    /// editions of an event overwrite each other
+    #[arg(long)]
+    no_subdir_edition: bool,
+    /// Octal mode of the created directories (Unix only)
+    #[arg(long, value_parser = perms::parse_mode)]
+    dir_mode: Option<u32>,
+    /// Octal mode of the written files (Unix only)
+    #[arg(long, value_parser = perms::parse_mode)]
+    file_mode: Option<u32>,
+    /// Delete the map files of the edition directory which aren't part of the edition anymore
+    #[arg(long)]
+    prune: bool,
+    /// Download only the first N maps of the edition, in the order of the API
+    #[arg(long, conflicts_with = "prune")]
+    limit: Option<usize>,
+    /// Download only the first N categories of the edition, in the order of the API, after
+    /// `--include-map` and `--include-map-glob` and before `--limit`
+    #[arg(long, value_name = "N", conflicts_with = "prune")]
+    limit_categories: Option<usize>,
+    /// Download only the map with this UID, can be repeated
+    #[arg(long = "include-map", value_name = "UID", conflicts_with = "prune")]
+    include_maps: Vec<String>,
+    /// Download only the maps whose UID matches this glob pattern, like `abc*`, can be repeated
+    #[arg(
+        long = "include-map-glob",
+        value_name = "PATTERN",
+        value_parser = parse_glob,
+        conflicts_with = "prune"
+    )]
+    include_map_globs: Vec<globset::Glob>,
+    /// Maximum number of requests in flight, shared by all the downloaded editions
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+    concurrency: u32,
+    /// Minimum delay between two consecutive requests to MX, across all the downloads
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    min_delay: Duration,
+    /// Maximum number of files written at the same time, shared by all the downloaded editions
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    parallel_writes: u32,
+    /// Maximum number of maps downloading or waiting to be written, in each edition. The
+    /// downloads pause when it's reached, so that a slow disk or a slow map doesn't make the
+    /// others pile up in memory
+    #[arg(long, value_name = "N", default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    write_queue: u32,
+    /// Maximum number of bytes of downloaded maps held in memory, whatever the concurrency. The
+    /// next downloads wait for the maps to be written when it would be exceeded, counting the
+    /// downloads in progress as large as the largest map so far, so a map larger than the
+    /// previous ones can exceed it
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    max_inflight_bytes: Option<u64>,
+    /// Number of threads running the downloads, the checksums and the validation, defaults to
+    /// the number of CPUs. It doesn't limit the number of downloads in flight, which is set by
+    /// `--concurrency`
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    worker_threads: Option<u32>,
+    /// Number of batch entries downloaded at the same time
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    parallel_editions: u32,
+    /// Maximum number of retries of a failed request
+    #[arg(long, default_value_t = 3)]
+    max_retries: u32,
+    /// Comma-separated failed statuses which are retried, instead of 429 and the 5xx. The
+    /// connection errors and timeouts are always retried
+    #[arg(
+        long = "retry-status",
+        value_name = "CODES",
+        value_delimiter = ',',
+        value_parser = parse_status
+    )]
+    retry_statuses: Vec<reqwest::StatusCode>,
+    /// Base delay of the exponential retry backoff: the n-th retry waits up to `base * 2^n`
+    #[arg(long, default_value = "500ms", value_parser = humantime::parse_duration)]
+    retry_base_delay: Duration,
+    /// Maximum delay between two retries
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    retry_max_delay: Duration,
+    /// Wait exactly `min(max, base * 2^n)` before the n-th retry, instead of a random delay
+    /// between zero and that value
+    #[arg(long)]
+    no_jitter: bool,
+    /// Retry the API requests whose response isn't valid JSON, instead of failing. Some proxies
+    /// truncate the responses now and then
+    #[arg(long)]
+    retry_on_parse_error: bool,
+    /// URL of the maps, instead of MX, with the placeholders `{mx_id}`, `{uid}`, `{version}`
+    /// and `{download_url}` (provided by the API for some events)
+    #[arg(long, value_parser = parse_url_template)]
+    map_url_template: Option<String>,
+    /// Only download the maps from MX and from this host, like `mirror.example.com`, also after
+    /// a redirection. Can be repeated
+    #[arg(long = "allowed-host", value_name = "HOST", value_parser = parse_host)]
+    allowed_hosts: Vec<String>,
+    /// Base URL of a mirror of MX, like `https://mirror.example.com`, from which the maps are
+    /// downloaded when MX fails after the retries
+    #[arg(long, value_name = "URL")]
+    mx_fallback: Option<reqwest::Url>,
+    /// User of the basic authentication of the maps downloaded from another host than MX with
+    /// `--map-url-template` or `--mx-fallback`, for a private mirror. The requests to MX stay
+    /// unauthenticated
+    #[arg(long, requires = "mirror")]
+    mx_user: Option<String>,
+    /// Password of `--mx-user`, better set through the environment. It's ignored without
+    /// `--mx-user`, so that it can stay set for the runs that don't download from the mirror
+    #[arg(long, env = "SOEVENT_MX_PASS", hide_env_values = true, value_parser = parse_secret)]
+    mx_pass: Option<Secret>,
+    /// Cache the event editions in `<out>/.soevent/cache`, and use that cache when the API
+    /// can't be reached
+    #[arg(long)]
+    prefer_cached_on_error: bool,
+    /// Write a `manifest.json` file describing the downloaded maps in the edition directory
+    #[arg(long)]
+    manifest: bool,
+    /// Skip the maps listed in the manifest of a previous, possibly interrupted, run
+    #[arg(long)]
+    resume_manifest: Option<PathBuf>,
+    /// Only download the maps which changed since the existing manifest of the edition was
+    /// written, according to their ETag
+    #[arg(long, requires = "manifest")]
+    conditional: bool,
+    /// Skip the editions whose existing manifest is complete and whose maps are all present,
+    /// without requesting them from the API
+    #[arg(long, requires = "manifest")]
+    skip_complete: bool,
+    /// Record where each map comes from in the manifest (URLs, status, cache headers, time)
+    #[arg(long, requires = "manifest")]
+    provenance: bool,
+    /// Write the same files from a run to another, to commit them with minimal diffs: the
+    /// manifest lists the maps of each category by UID and omits the download time of
+    /// `--provenance`, and the file names can't depend on MX or on the position of the maps,
+    /// `--naming mx` and `--naming index-uid` are rejected. The `SHA256SUMS` and `--csv` files
+    /// are always sorted by path
+    #[arg(long, conflicts_with = "zip")]
+    reproducible: bool,
+    /// Shell command run after each written map, with its path as first argument
+    #[arg(long)]
+    on_map: Option<String>,
+    /// Shell command run after each downloaded edition, with its directory as first argument
+    #[arg(long)]
+    on_complete: Option<String>,
+    /// IP version used to connect to the hosts, `auto` lets the system decide
+    #[arg(long, value_enum, default_value_t = IpVersion::Auto)]
+    ip_version: IpVersion,
+    /// Accept any TLS certificate, like the self-signed one of a staging API. Never use it to
+    /// download from the real hosts, anyone on the way could alter the maps
+    #[arg(long, alias = "insecure")]
+    no_verify_tls: bool,
+    /// Also trust the root certificates of this PEM file, like the one of an internal CA
+    /// terminating TLS in front of the API and MX
+    #[arg(long, value_name = "PATH", conflicts_with = "no_verify_tls")]
+    ca_cert: Option<PathBuf>,
+    /// Maximum number of idle connections kept open to each host, unbounded by default
+    #[arg(long, value_name = "N")]
+    pool_max_idle: Option<usize>,
+    /// Time after which an idle connection is closed
+    #[arg(long, value_name = "DURATION", default_value = "90s", value_parser = humantime::parse_duration)]
+    pool_idle_timeout: Duration,
+    /// Only log the warnings and the errors, and print a summary at the end
+    #[arg(long, global = true, conflicts_with = "quiet")]
+    summary_only: bool,
+    /// Print the progress of the downloads on stdout as they happen, one JSON object per line
+    /// with its kind in `event`. The logs and the output of the hooks go to stderr
+    #[arg(long, conflicts_with_all = ["summary_only", "dry_run"])]
+    events_jsonl: bool,
+    /// Print the report of the run on stdout at the end as JSON, on a single line, with the
+    /// skipped maps and the reason of each skip. Also prints the result of `compare-editions`
+    /// and `list-categories` as JSON
+    #[arg(long)]
+    output_json: bool,
+    /// Only log the errors
+    #[arg(long, short, global = true)]
+    quiet: bool,
+    /// Print each request to the API and MX as an equivalent `curl` command on stderr, without
+    /// its credentials, before sending it
+    #[arg(long, global = true)]
+    dump_requests: bool,
+    /// Also write all the logs and spans, with the requests, their statuses, the retries and
+    /// timings, to this file, whatever the verbosity of the console
+    #[arg(long, global = true, value_name = "PATH")]
+    debug_log: Option<PathBuf>,
+    /// How a fatal error is printed on stderr, `json` prints its kind, message, URL and status
+    #[arg(long, value_enum, global = true, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+    /// Treat recoverable problems, like failing hooks, as errors
+    #[arg(long)]
+    strict: bool,
+    /// Succeed when an edition has no map to download, usually the sign of a wrong event
+    /// handle or of filters matching nothing
+    #[arg(long)]
+    allow_empty: bool,
+    /// Also download the thumbnail of each map, written next to it
+    #[arg(long)]
+    thumbnails: bool,
+    /// Check that the UID in the header of each downloaded map is the one listed by the API
+    #[arg(long)]
+    validate_uid: bool,
+    /// Write the category, UID, MX ID, path relative to the output directory and size of the
+    /// downloaded maps to this CSV file at the end of the run
+    #[arg(long, value_name = "PATH")]
+    csv: Option<PathBuf>,
+    /// Write metrics about each downloaded edition to this file at the end of the run, in the
+    /// Prometheus text format
+    #[arg(long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+    /// Before downloading an edition, check that MX has all its maps with HEAD requests. The
+    /// missing maps are skipped, or fail the edition with `--strict`
+    #[arg(long)]
+    head_check: bool,
+    /// Write a `SHA256SUMS` file next to the downloaded maps
+    #[arg(long)]
+    checksums: bool,
+    /// Store the maps once in this directory, under their SHA-256, and link them from the
+    /// edition directories. The maps already in the store aren't downloaded again
+    #[arg(long)]
+    cas_store: Option<PathBuf>,
+
+    /// Base URL of the API, or `unix:<path>` to connect to a Unix socket
+    #[cfg(feature = "localhost_test")]
+    #[arg(long, global = true, default_value = "http://localhost:3001")]
+    host: String,
+    /// Save the responses of the API and MX to fixture files in this directory
+    #[cfg(feature = "localhost_test")]
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// Answer the requests with the fixture files saved by `--record` in this directory,
+    /// without sending them
+    #[cfg(feature = "localhost_test")]
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<PathBuf>,
+}
+
+impl Default for Command {
+    /// The options of the command without arguments, ignoring the environment variables.
+    fn default() -> Self {
+        use clap::{CommandFactory as _, FromArgMatches as _};
+
+        let matches = Self::command()
+            .mut_args(|arg| arg.env(None))
+            .try_get_matches_from(["soevent"])
+            .expect("the default options are valid");
+        Self::from_arg_matches(&matches).expect("the default options are valid")
+    }
+}
+
+impl Command {
+    /// Sets the output directory.
+    pub fn out<S: Into<String>>(mut self, out: S) -> Self {
+        self.out = out.into();
+        self
+    }
+
+    /// Sets the maximum number of requests in flight, at least one.
+    pub fn concurrency(mut self, concurrency: u32) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Returns the most verbose level of the logs.
+    #[cfg(feature = "tracing")]
+    pub fn max_log_level(&self) -> tracing::level_filters::LevelFilter {
+        use tracing::level_filters::LevelFilter;
+
+        if self.quiet {
+            LevelFilter::ERROR
+        } else if self.summary_only {
+            LevelFilter::WARN
+        } else {
+            LevelFilter::INFO
+        }
+    }
+
+    /// Returns the path of the debug log, if set.
+    pub fn debug_log(&self) -> Option<&Path> {
+        self.debug_log.as_deref()
+    }
+
+    /// Returns the number of worker threads of the runtime, if set.
+    pub fn worker_threads(&self) -> Option<usize> {
+        self.worker_threads.map(|threads| threads as _)
+    }
+
+    /// Returns whether fatal errors are printed as JSON.
+    pub fn json_errors(&self) -> bool {
+        matches!(self.error_format, ErrorFormat::Json)
+    }
+
+    /// Sets whether the maps whose file already exists are downloaded again.
+    pub fn skip_existing(mut self, skip_existing: bool) -> Self {
+        self.skip_existing = skip_existing;
+        self
+    }
+}
+
+fn parse_separator(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(['/', '\\']) {
+        Err("the separator must be non-empty and must not contain path separators".to_owned())
+    } else {
+        Ok(s.to_owned())
+    }
+}
+
+fn parse_map_ext(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(['/', '\\']) {
+        Err("the extension must be non-empty and must not contain path separators".to_owned())
+    } else {
+        Ok(s.to_owned())
+    }
+}
+
+fn parse_date(s: &str) -> Result<SystemTime, String> {
+    let s = s.trim();
+    let res = if s.len() == "YYYY-MM-DD".len() {
+        humantime::parse_rfc3339_weak(&format!("{s} 00:00:00"))
+    } else {
+        humantime::parse_rfc3339_weak(s)
+    };
+    res.map_err(|e| format!("{e}, expected a date like `2024-05-01` or `2024-05-01 12:00:00`"))
+}
+
+fn parse_status(s: &str) -> Result<reqwest::StatusCode, String> {
+    s.trim()
+        .parse::<u16>()
+        .ok()
+        .and_then(|code| reqwest::StatusCode::from_u16(code).ok())
+        .filter(|status| status.is_client_error() || status.is_server_error())
+        .ok_or_else(|| format!("`{s}` isn't a failed HTTP status, between 400 and 599"))
+}
+
+/// A password, hidden when the configuration is printed.
+#[derive(Clone)]
+struct Secret(String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"REDACTED\"")
+    }
+}
+
+fn parse_secret(s: &str) -> Result<Secret, String> {
+    Ok(Secret(s.to_owned()))
+}
+
+fn parse_host(s: &str) -> Result<String, String> {
+    let host = s.to_ascii_lowercase();
+    reqwest::Url::parse(&format!("https://{host}"))
+        .ok()
+        .filter(|url| url.host_str() == Some(&host))
+        .map(|_| host)
+        .ok_or_else(|| format!("`{s}` isn't a host name, like `mirror.example.com`"))
+}
+
+/// Expands the `$VAR` and `${VAR}` references to environment variables, and `%VAR%` on Windows.
+///
+/// A `$` or `%` which doesn't start a reference is kept as is, and doubling it escapes it.
+fn expand_env(s: &str) -> Result<String, String> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    // A name starting with a digit, like in `$1`, isn't a variable
+    let is_var = |name: &str| name.starts_with(|c: char| !c.is_ascii_digit());
+    let sigils: &[char] = if cfg!(windows) { &['$', '%'] } else { &['$'] };
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find(sigils) {
+        out.push_str(&rest[..i]);
+        let sigil = &rest[i..i + 1];
+        let after = &rest[i + 1..];
+        if let Some(tail) = after.strip_prefix(sigil) {
+            // `$$` and `%%` are a literal `$` or `%`
+            out.push_str(sigil);
+            rest = tail;
+            continue;
+        }
+        let (name, tail) = match sigil {
+            "$" if after.starts_with('{') => match after.find('}') {
+                Some(end) if end > 1 => (&after[1..end], &after[end + 1..]),
+                _ => return Err("`${` must be followed by a variable name and `}`".to_owned()),
+            },
+            "$" => {
+                let end = after.find(|c| !is_name(c)).unwrap_or(after.len());
+                match &after[..end] {
+                    name if is_var(name) => (name, &after[end..]),
+                    _ => ("", after),
+                }
+            }
+            _ => match after.find('%') {
+                Some(end) if is_var(&after[..end]) && after[..end].chars().all(is_name) => {
+                    (&after[..end], &after[end + 1..])
+                }
+                _ => ("", after),
+            },
+        };
+        if name.is_empty() {
+            out.push_str(sigil);
+        } else {
+            out += &std::env::var(name)
+                .map_err(|_| format!("environment variable `{name}` isn't set"))?;
+        }
+        rest = tail;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// How the map files are laid out in the edition directory.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Layout {
+    /// `<category>/<name>.Map.Gbx`
+    Nested,
+    /// `<name>.Map.Gbx`, the maps of all the categories in the same directory
+    Flat,
+    /// `<category><sep><name>.Map.Gbx`
+    Prefixed,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Naming {
+    /// `<uid>.Map.Gbx`
+    Uid,
+    /// `<mx_id>.Map.Gbx`
+    #[value(name = "mxid")]
+    MxId,
+    /// `<uid>_<mx_id>.Map.Gbx`
+    #[value(name = "uid-mxid")]
+    UidMxId,
+    /// `<index>_<uid>.Map.Gbx`, the position of the map in its category padded to the same
+    /// width for all its maps. The maps of `--map-list` aren't numbered
+    #[value(name = "index-uid")]
+    IndexUid,
+    /// The file name sent by MX, `<uid>.Map.Gbx` if it sends none, a name that doesn't end in
+    /// `.Gbx` or `--map-ext`, or the path of another file. It's only known once the map is
+    /// downloaded, so the options looking for the previous files, like `--skip-existing`,
+    /// look for `<uid>.Map.Gbx`
+    Mx,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Sort {
+    /// The order of the API response
+    Api,
+    Uid,
+    #[value(name = "mxid")]
+    MxId,
+    /// The names given by the API, the maps without one last
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum DryRun {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum IpVersion {
+    Auto,
+    V4,
+    V6,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Subcommand {
+    /// Check the connectivity to the API and MX hosts, and print the effective configuration
+    Doctor,
+    /// Print the version, the enabled features, the compiled-in hosts and the effective
+    /// configuration, to attach to a bug report
+    Version,
+    /// Print the MX download URLs of a map, or of all the maps of an event edition, without
+    /// downloading them
+    PrintUrl {
+        /// MX ID of the map
+        #[arg(long, conflicts_with_all = ["event_handle", "event_edition"])]
+        mx_id: Option<MxId>,
+        event_handle: Option<String>,
+        event_edition: Option<u32>,
+    },
+    /// Print a shell script downloading the maps of an event edition with `curl`, to the paths
+    /// and with the filters of a download, to run where the tool can't be. The password of
+    /// `--mx-user` is read from `SOEVENT_MX_PASS` when the script runs
+    ExportScript {
+        event_handle: Option<String>,
+        event_edition: Option<u32>,
+    },
+    /// Print the maps added, removed and moved between categories from an edition of an event to
+    /// another, without downloading them
+    CompareEditions {
+        event_handle: String,
+        from: u32,
+        to: u32,
+        /// Print the differences as JSON, on a single line
+        #[arg(long, alias = "output-json")]
+        json: bool,
+    },
+    /// Print the categories of an event edition, or of its last edition, with their number of
+    /// maps, without downloading them
+    ListCategories {
+        event_handle: Option<String>,
+        event_edition: Option<u32>,
+        /// Print the categories as JSON, on a single line
+        #[arg(long, alias = "output-json")]
+        json: bool,
+    },
+}
+
+#[cfg(all(debug_assertions, feature = "localhost_test"))]
+#[inline(always)]
+fn api_host(args: &Command) -> &str {
+    if args.host.starts_with("unix:") {
+        // The client connects to the socket whatever the host of the URL
+        "http://localhost"
+    } else {
+        &args.host
+    }
+}
+
+#[cfg(not(all(debug_assertions, feature = "localhost_test")))]
+#[inline(always)]
+fn api_host(_: &Command) -> &str {
+    API_HOST
+}
+
+/// Whether `name` sent by MX ends with a map extension, anything else could overwrite a file
+/// the tool writes itself.
+fn is_map_file_name(map_ext: &str, name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [map_ext, ".map.gbx", ".gbx"].iter().any(|ext| {
+        let ext = ext.to_ascii_lowercase();
+        name.len() > ext.len() && name.ends_with(&ext)
+    })
+}
+
+fn map_file_name(args: &Command, map: &Map) -> String {
+    if let (Naming::Mx, Some(name)) = (args.naming, &map.mx_file_name) {
+        return name.clone();
+    }
+    let name = match args.naming {
+        Naming::Uid | Naming::Mx => map.map_uid.clone(),
+        Naming::MxId => map.mx_id.to_string(),
+        Naming::UidMxId => format!("{}_{}", map.map_uid, map.mx_id),
+        Naming::IndexUid => match map.index {
+            Some((index, width)) => format!("{index:0width$}_{}", map.map_uid),
+            None => map.map_uid.clone(),
+        },
+    };
+    format!("{name}{}", args.map_ext)
+}
+
+/// Returns the path of the map file, relative to the edition directory.
+fn map_rel_path(args: &Command, cat_handle: &str, map: &Map) -> PathBuf {
+    let name = map_file_name(args, map);
+    match args.layout {
+        Layout::Nested => PathBuf::from(cat_handle).join(name),
+        Layout::Flat => PathBuf::from(name),
+        Layout::Prefixed => PathBuf::from(format!("{cat_handle}{}{name}", args.prefix_sep)),
+    }
+}
+
+fn build_client(args: &Command) -> anyhow::Result<reqwest::Client> {
+    // Binding to the unspecified address of a family only keeps the addresses of that family
+    let local_address = match args.ip_version {
+        IpVersion::Auto => None,
+        IpVersion::V4 => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        IpVersion::V6 => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    };
+    let mut builder = reqwest::Client::builder()
+        .local_address(local_address)
+        .pool_idle_timeout(args.pool_idle_timeout);
+    if let Some(max_idle) = args.pool_max_idle {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(path) = &args.ca_cert {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Unable to read CA certificate `{}`", path.display()))?;
+        let certs = reqwest::Certificate::from_pem_bundle(&pem)
+            .ok()
+            .filter(|certs| !certs.is_empty())
+            .with_context(|| format!("Invalid CA certificate `{}`", path.display()))?;
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if args.no_verify_tls {
+        // Printed even with `--quiet` or the logs turned off, it can't go unnoticed
+        eprintln!(
+            "WARNING: TLS CERTIFICATES AREN'T VERIFIED (`--no-verify-tls`): the API and MX responses can \
+             be altered by anyone on the network"
+        );
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder.build().context("Unable to build the HTTP client")
+}
+
+/// Returns the client used for the requests to the API, connected to the `--host unix:<path>`
+/// socket if provided.
+#[cfg(all(debug_assertions, feature = "localhost_test", unix))]
+fn build_api_client(args: &Command, client: &reqwest::Client) -> anyhow::Result<reqwest::Client> {
+    match args.host.strip_prefix("unix:") {
+        Some(path) => reqwest::Client::builder()
+            .unix_socket(path)
+            .build()
+            .context("Unable to build the API HTTP client"),
+        None => Ok(client.clone()),
+    }
+}
+
+#[cfg(not(all(debug_assertions, feature = "localhost_test", unix)))]
+fn build_api_client(_: &Command, client: &reqwest::Client) -> anyhow::Result<reqwest::Client> {
+    Ok(client.clone())
+}
+
+/// The state shared by all the downloads of a run.
+pub struct Context {
+    client: reqwest::Client,
+    api_client: reqwest::Client,
+    args: Command,
+    retry: retry::Policy,
+    /// Bounds the number of requests in flight across all the editions.
+    limiter: tokio::sync::Semaphore,
+    /// Spaces out the requests to MX.
+    mx_pacer: pacing::Pacer,
+    /// Set when a write failed because the disk is full, there's no point downloading more.
+    disk_full: AtomicBool,
+    reporter: Option<Box<dyn progress::Reporter>>,
+    store: Option<Arc<cas::Store>>,
+    /// The map downloads in progress, so that a map listed several times is downloaded once.
+    map_downloads: Mutex<HashMap<DownloadKey, Arc<SharedDownload>>>,
+    /// Bounds the number of files written at the same time.
+    write_limiter: tokio::sync::Semaphore,
+    mx_in_flight: stats::InFlight,
+    memory: Arc<stats::Memory>,
+    bandwidth: stats::Bandwidth,
+    metrics: metrics::Metrics,
+    index: index::Index,
+    failures: error::Failures,
+    skips: stats::Skips,
+}
+
+impl Context {
+    pub fn new(args: Command) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            !args.reproducible || !matches!(args.naming, Naming::Mx | Naming::IndexUid),
+            "`--naming mx` and `--naming index-uid` don't give stable file names, they can't be \
+             used with `--reproducible`"
+        );
+        if args.no_subdir_edition {
+            log::warn!(
+                "The editions of an event are written in the same directory, \
+                use `--prune` to remove the maps of the previous editions"
+            );
+        }
+        curl::set_dump(args.dump_requests);
+        #[cfg(feature = "localhost_test")]
+        vcr::set_mode(match (&args.record, &args.replay) {
+            (Some(dir), _) => Some(vcr::Mode::Record(dir.clone())),
+            (_, Some(dir)) => Some(vcr::Mode::Replay(dir.clone())),
+            _ => None,
+        });
+        let client = build_client(&args)?;
+        Ok(Self {
+            api_client: build_api_client(&args, &client)?,
+            client,
+            retry: retry::Policy {
+                max_retries: args.max_retries,
+                base_delay: args.retry_base_delay,
+                max_delay: args.retry_max_delay,
+                jitter: !args.no_jitter,
+                parse_errors: args.retry_on_parse_error,
+                statuses: (!args.retry_statuses.is_empty()).then(|| args.retry_statuses.clone()),
+            },
+            limiter: tokio::sync::Semaphore::new(args.concurrency as _),
+            mx_pacer: pacing::Pacer::new(args.min_delay),
+            disk_full: AtomicBool::new(false),
+            reporter: args
+                .events_jsonl
+                .then(|| Box::new(progress::JsonLines) as Box<dyn progress::Reporter>),
+            mx_in_flight: stats::InFlight::default(),
+            memory: Arc::new(stats::Memory::new(args.max_inflight_bytes)),
+            bandwidth: stats::Bandwidth::default(),
+            metrics: metrics::Metrics::default(),
+            index: index::Index::default(),
+            failures: error::Failures::default(),
+            skips: stats::Skips::default(),
+            map_downloads: Mutex::default(),
+            write_limiter: tokio::sync::Semaphore::new(args.parallel_writes as _),
+            store: args
+                .cas_store
+                .as_deref()
+                .map(|dir| cas::Store::open(dir).map(Arc::new))
+                .transpose()?,
+            args,
+        })
+    }
+
+    /// Uses this HTTP client instead of the default one.
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.api_client = client.clone();
+        self.client = client;
+        self
+    }
+
+    /// Sets the reporter receiving the progress of the downloads.
+    pub fn with_reporter<R: progress::Reporter + 'static>(mut self, reporter: R) -> Self {
+        self.reporter = Some(Box::new(reporter));
+        self
+    }
+
+    fn report(&self, event: progress::Event) {
+        if let Some(reporter) = &self.reporter {
+            reporter.report(event);
+        }
+    }
+
+    fn api_host(&self) -> &str {
+        api_host(&self.args)
+    }
+}
+
+/// The ID of a map or of the mappack of an edition on MX.
+///
+/// The IDs are never negative, so a negative or too large ID in a response or an argument fails
+/// to parse instead of wrapping.
+type MxId = u32;
+
+#[derive(serde::Deserialize)]
+struct Map {
+    mx_id: MxId,
+    map_uid: String,
+    /// The MX version of the map shipped with the edition, the latest one is used if missing.
+    #[serde(default)]
+    mx_version: Option<u32>,
+    /// Where to download the map, for the events hosting maps outside MX.
+    #[serde(default)]
+    download_url: Option<String>,
+    /// The SHA-256 of the map file, checked against the downloaded content when provided.
+    #[serde(default)]
+    sha256: Option<String>,
+    /// The name of the map, if the API provides it.
+    #[serde(default)]
+    name: Option<String>,
+    /// The 1-based position of the map in its category with `--naming index-uid`, and the
+    /// number of digits it's padded to.
+    #[serde(skip)]
+    index: Option<(usize, usize)>,
+    /// The file name sent by MX with the map, used with `--naming mx`.
+    #[serde(skip)]
+    mx_file_name: Option<String>,
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (MX ID: {}", self.map_uid, self.mx_id)?;
+        if let Some(version) = self.mx_version {
+            write!(f, ", version {version}")?;
+        }
+        f.write_str(")")
+    }
+}
+
+const URL_PLACEHOLDERS: &[&str] = &["mx_id", "uid", "version", "download_url"];
+
+fn parse_glob(s: &str) -> Result<globset::Glob, String> {
+    globset::Glob::new(s).map_err(|e| e.kind().to_string())
+}
+
+fn parse_url_template(s: &str) -> Result<String, String> {
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return Err("unclosed `{` in the template".to_owned());
+        };
+        let name = &rest[start + 1..start + len];
+        if !URL_PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder `{{{name}}}`, expected one of: {}",
+                URL_PLACEHOLDERS.join(", ")
+            ));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(s.to_owned())
+}
+
+fn map_url(args: &Command, map: &Map) -> anyhow::Result<String> {
+    let Some(template) = &args.map_url_template else {
+        return Ok(match map.mx_version {
+            Some(version) => format!("{MX_HOST}/maps/download/{}?version={version}", map.mx_id),
+            None => format!("{MX_HOST}/maps/download/{}", map.mx_id),
+        });
+    };
+    let mut url = template
+        .replace("{mx_id}", &map.mx_id.to_string())
+        .replace("{uid}", &map.map_uid)
+        .replace(
+            "{version}",
+            &map.mx_version.map(|v| v.to_string()).unwrap_or_default(),
+        );
+    if url.contains("{download_url}") {
+        let download_url = map
+            .download_url
+            .as_deref()
+            .with_context(|| format!("The API provides no download URL for map {map}"))?;
+        url = url.replace("{download_url}", download_url);
+    }
+    Ok(url)
+}
+
+#[derive(serde::Deserialize)]
+struct Category {
+    handle: String,
+    maps: Vec<Map>,
+}
+
+#[derive(serde::Deserialize)]
+struct EventEdition {
+    name: String,
+    mx_id: MxId,
+    categories: Vec<Category>,
+}
+
+impl fmt::Display for EventEdition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (MX ID: {})", self.name, self.mx_id)
+    }
+}
+
+struct DownloadedMap {
+    map: Map,
+    content: bytes::Bytes,
+    sha256: Option<String>,
+    /// `None` if the map comes from the store.
+    provenance: Option<manifest::Provenance>,
+    thumbnail: Option<Thumbnail>,
+    etag: Option<String>,
+    /// The entry of the previous manifest, if MX answered that the map didn't change.
+    unchanged: Option<manifest::Entry>,
+    /// Released once the map is written.
+    _memory: stats::MemoryGuard,
+}
+
+struct Thumbnail {
+    extension: &'static str,
+    content: bytes::Bytes,
+}
+
+#[derive(Clone)]
+struct MxResponse {
+    /// Empty if the server answered that the content didn't change.
+    content: bytes::Bytes,
+    not_modified: bool,
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    /// The file name given in `Content-Disposition`, sanitized.
+    file_name: Option<String>,
+    provenance: manifest::Provenance,
+}
+
+/// Fails if the URL isn't on MX or on one of the `--allowed-host` hosts, when provided.
+fn check_host(args: &Command, url: &reqwest::Url) -> anyhow::Result<()> {
+    if args.allowed_hosts.is_empty() {
+        return Ok(());
+    }
+    let host = url.host_str().unwrap_or_default();
+    let mx_host = reqwest::Url::parse(MX_HOST).ok();
+    anyhow::ensure!(
+        mx_host.as_ref().and_then(|url| url.host_str()) == Some(host)
+            || args.mx_fallback.as_ref().and_then(|url| url.host_str()) == Some(host)
+            || args.allowed_hosts.iter().any(|allowed| allowed == host),
+        "Refusing to download from `{host}`, which isn't an allowed host"
+    );
+    Ok(())
+}
+
+/// Returns the URL of a map on the `--mx-fallback` mirror, with the same path and query.
+fn fallback_url(fallback: &reqwest::Url, url: &str) -> anyhow::Result<String> {
+    let url = reqwest::Url::parse(url).with_context(|| format!("Invalid map URL `{url}`"))?;
+    let mut fallback = fallback.clone();
+    let path = format!("{}{}", fallback.path().trim_end_matches('/'), url.path());
+    fallback.set_path(&path);
+    fallback.set_query(url.query());
+    Ok(fallback.to_string())
+}
+
+/// Returns the user of `--mx-user` to authenticate a request for a map, unless it's sent to MX.
+fn mx_auth_user<'a>(args: &'a Command, url: &str) -> Option<&'a str> {
+    let user = args.mx_user.as_deref()?;
+    let host = |url: &str| reqwest::Url::parse(url).ok()?.host_str().map(str::to_owned);
+    (host(url) != host(MX_HOST)).then_some(user)
+}
+
+/// Adds the basic authentication of `--mx-user` to a request for a map, unless it's sent to MX.
+fn with_mx_auth(
+    args: &Command,
+    url: &str,
+    req: reqwest::RequestBuilder,
+) -> reqwest::RequestBuilder {
+    match mx_auth_user(args, url) {
+        Some(user) => req.basic_auth(user, args.mx_pass.as_ref().map(|pass| &pass.0)),
+        None => req,
+    }
+}
+
+/// Sends a GET request to MX, following the concurrency, pacing and retry settings.
+///
+/// If an ETag is provided, the server can answer that the content didn't change.
+async fn fetch_mx(ctx: &Context, url: &str, etag: Option<&str>) -> anyhow::Result<MxResponse> {
+    if let Ok(url) = reqwest::Url::parse(url) {
+        check_host(&ctx.args, &url)?;
+    }
+    retry::run(&ctx.retry, || async {
+        let _permit = ctx
+            .limiter
+            .acquire()
+            .await
+            .map_err(|e| retry::Error::Permanent(e.into()))?;
+        ctx.mx_pacer.wait().await;
+        let _in_flight = ctx.mx_in_flight.enter();
+        let mut req = with_mx_auth(
+            &ctx.args,
+            url,
+            ctx.client.get(url).header("User-Agent", USER_AGENT),
+        );
+        if let Some(etag) = etag {
+            req = req.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let res = ctx.retry.check(curl::send(req).await)?;
+        // Before reading the map, the host can be another one after a redirection
+        check_host(&ctx.args, res.url()).map_err(retry::Error::Permanent)?;
+        let not_modified = res.status() == reqwest::StatusCode::NOT_MODIFIED;
+        let provenance = manifest::Provenance::new(url, &res, !ctx.args.reproducible);
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        let content_type = header(reqwest::header::CONTENT_TYPE);
+        let content_encoding = header(reqwest::header::CONTENT_ENCODING);
+        let file_name = res
+            .headers()
+            .get(reqwest::header::CONTENT_DISPOSITION)
+            .and_then(|v| disposition::file_name(&String::from_utf8_lossy(v.as_bytes())));
+        let final_url = res.url().clone();
+        let content = res.bytes().await.map_err(retry::Error::transient)?;
+        ctx.bandwidth.add(&final_url, content.len());
+        Ok(MxResponse {
+            content,
+            not_modified,
+            content_type,
+            content_encoding,
+            file_name,
+            provenance,
+        })
+    })
+    .await
+}
+
+/// The URL and the ETag of a request.
+type DownloadKey = (String, Option<String>);
+type SharedDownload = tokio::sync::OnceCell<Result<MxResponse, Arc<anyhow::Error>>>;
+
+/// Removes a request from the ones in progress when its first caller is done with it, even if
+/// its future is dropped, so that the later callers don't get its result.
+struct InProgress<'a> {
+    downloads: &'a Mutex<HashMap<DownloadKey, Arc<SharedDownload>>>,
+    key: DownloadKey,
+}
+
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        self.downloads.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Same as [`fetch_mx`], but waits for the result of the identical request in progress, if any.
+async fn fetch_mx_coalesced(
+    ctx: &Context,
+    url: &str,
+    etag: Option<&str>,
+) -> anyhow::Result<MxResponse> {
+    let key = (url.to_owned(), etag.map(str::to_owned));
+    let (cell, in_progress) = {
+        let mut downloads = ctx.map_downloads.lock().unwrap();
+        match downloads.get(&key) {
+            Some(cell) => (cell.clone(), None),
+            None => {
+                let cell = Arc::new(SharedDownload::new());
+                downloads.insert(key.clone(), cell.clone());
+                let in_progress = InProgress {
+                    downloads: &ctx.map_downloads,
+                    key,
+                };
+                (cell, Some(in_progress))
+            }
+        }
+    };
+    if in_progress.is_none() {
+        log::info!("Same request in progress, waiting for its result");
+    }
+    let res = cell
+        .get_or_init(|| async { fetch_mx(ctx, url, etag).await.map_err(Arc::new) })
+        .await
+        .clone();
+    res.map_err(|e| error::Shared(e).into())
+}
+
+/// Downloads the thumbnail of a map, a failure only skips it since the map itself is fine.
+async fn download_thumbnail(ctx: &Context, map: &Map) -> Option<Thumbnail> {
+    let url = format!("{MX_HOST}/maps/screenshot_normal/{}", map.mx_id);
+    match fetch_mx(ctx, &url, None).await {
+        Ok(res) => Some(Thumbnail {
+            extension: match res.content_type.as_deref() {
+                Some("image/png") => "png",
+                Some("image/webp") => "webp",
+                _ => "jpg",
+            },
+            content: res.content,
+        }),
+        Err(e) if retry::http_status(&e) == Some(reqwest::StatusCode::NOT_FOUND) => {
+            log::warn!("No thumbnail available for map {map}");
+            None
+        }
+        Err(e) => {
+            log::warn!("Unable to download the thumbnail of map {map}, skipping it: {e:#}");
+            None
+        }
+    }
+}
+
+/// Hashes the content on the blocking thread pool, so that big maps don't stall the other
+/// downloads running on the same worker.
+/// Stops the run on a full disk, the next editions and batch entries aren't tried either.
+fn stop_if_disk_full(ctx: &Context, e: anyhow::Error, written: usize) -> anyhow::Error {
+    if !e.is::<output::DiskFull>() {
+        return e;
+    }
+    ctx.disk_full.store(true, Ordering::Relaxed);
+    e.context(format!(
+        "Stopped downloading, {written} map(s) were written before the failure"
+    ))
+}
+
+/// Returns the line of a file in `SHA256SUMS`, in the format checked by `sha256sum -c`, with
+/// `/` separators on every platform.
+fn sums_line(sha256: &str, rel_path: &Path) -> String {
+    format!("{sha256}  {}\n", archive::entry_name(rel_path))
+}
+
+async fn sha256_of(content: bytes::Bytes) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        sha2::Sha256::digest(&content)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    })
+    .await
+    .context("Checksum task panicked")
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(ctx, category, previous), fields(cat.handle = %category, map = %map), err))]
+async fn download_map(
+    ctx: &Context,
+    category: &str,
+    mut map: Map,
+    previous: Option<manifest::Entry>,
+) -> anyhow::Result<DownloadedMap> {
+    // Before anything else, so that the maps wait in the order they're started
+    let admission = ctx.memory.admit().await;
+    anyhow::ensure!(
+        !ctx.disk_full.load(Ordering::Relaxed),
+        "Download cancelled, the disk is full"
+    );
+    log::info!("Downloading map...");
+    ctx.report(progress::Event::MapStarted {
+        category: category.to_owned(),
+        map_uid: map.map_uid.clone(),
+    });
+
+    let stored = match &ctx.store {
+        Some(store) => store.get(&map)?,
+        None => None,
+    };
+    let (content, sha256, provenance) = match stored {
+        Some((sha256, content)) => {
+            log::info!("Map found in the store");
+            (content, Some(sha256), None)
+        }
+        None => {
+            let url = map_url(&ctx.args, &map)?;
+            let etag = previous.as_ref().and_then(|entry| entry.etag.as_deref());
+            let MxResponse {
+                content,
+                not_modified,
+                content_type,
+                content_encoding,
+                file_name,
+                provenance,
+            } = match (
+                fetch_mx_coalesced(ctx, &url, etag).await,
+                &ctx.args.mx_fallback,
+            ) {
+                (Err(e), Some(fallback)) => {
+                    let url = fallback_url(fallback, &url)?;
+                    log::warn!("Unable to download map: {e:#}, trying the fallback `{url}`");
+                    let res = fetch_mx_coalesced(ctx, &url, etag)
+                        .await
+                        .with_context(|| format!("Unable to download map from `{url}` either"))?;
+                    log::info!("Map downloaded from the fallback `{url}`");
+                    res
+                }
+                (res, _) => res?,
+            };
+            if not_modified {
+                log::info!("Map unchanged since the previous download");
+                return Ok(DownloadedMap {
+                    _memory: admission.hold(content.len() as _),
+                    map,
+                    content,
+                    sha256: None,
+                    etag: provenance.etag.clone(),
+                    provenance: Some(provenance),
+                    thumbnail: None,
+                    unchanged: previous,
+                });
+            }
+            gbx::check_content_type(content_type.as_deref())?;
+            let content = gbx::decode_body(content, content_encoding.as_deref())?;
+            map.mx_file_name = file_name.filter(|name| {
+                let valid = is_map_file_name(&ctx.args.map_ext, name);
+                if !valid {
+                    log::warn!("MX sent the file name {name:?} for {map}, naming it after its UID");
+                }
+                valid
+            });
+            if ctx.args.validate_uid {
+                let uid = gbx::map_uid(&content).context("Unable to read the UID of the map")?;
+                anyhow::ensure!(
+                    uid == map.map_uid,
+                    error::Invalid(format!(
+                        "MX served the map `{uid}` for {map}, it wasn't written"
+                    ))
+                );
+            }
+            let sha256 = if ctx.args.checksums || ctx.store.is_some() || map.sha256.is_some() {
+                Some(sha256_of(content.clone()).await?)
+            } else {
+                None
+            };
+            (content, sha256, Some(provenance))
+        }
+    };
+    if let (Some(expected), Some(sha256)) = (&map.sha256, &sha256) {
+        anyhow::ensure!(
+            expected.eq_ignore_ascii_case(sha256),
+            error::Invalid(format!(
+                "The SHA-256 of {map} is {sha256} instead of {expected}, it wasn't written"
+            ))
+        );
+    }
+
+    let thumbnail = if ctx.args.thumbnails {
+        download_thumbnail(ctx, &map).await
+    } else {
+        None
+    };
+
+    let held = content.len() + thumbnail.as_ref().map_or(0, |t| t.content.len());
+    Ok(DownloadedMap {
+        _memory: admission.hold(held as _),
+        map,
+        content,
+        sha256,
+        etag: provenance.as_ref().and_then(|p| p.etag.clone()),
+        provenance,
+        thumbnail,
+        unchanged: None,
+    })
+}
+
+/// A downloaded map with the handle of its category, and its slot in the write queue.
+type QueuedMap<'a> = (String, DownloadedMap, tokio::sync::SemaphorePermit<'a>);
+
+/// Downloads the maps of a category, yielded in their order with the handle of the category.
+///
+/// Each map takes a slot of `queue` before its download and keeps it until it's written, so
+/// that the maps done behind a slower one don't pile up in memory.
+fn download_category<'a>(
+    ctx: &'a Context,
+    cat: Category,
+    previous: &'a HashMap<(String, String), manifest::Entry>,
+    queue: &'a tokio::sync::Semaphore,
+) -> impl futures::Stream<Item = anyhow::Result<QueuedMap<'a>>> + 'a {
+    log::info!("Downloading maps of category `{}`...", cat.handle);
+
+    let maps_len = cat.maps.len();
+    let handle = cat.handle;
+    futures::stream::iter(cat.maps)
+        .map(move |map| {
+            let previous = previous
+                .get(&(handle.clone(), map.map_uid.clone()))
+                .cloned();
+            let handle = handle.clone();
+            async move {
+                let map_uid = map.map_uid.clone();
+                let context = format!("Unable to download map {map} of category `{handle}`");
+                // The maps of a category take their slot in order, the first one never waits
+                // for the ones after it
+                let slot = queue.acquire().await?;
+                match download_map(ctx, &handle, map, previous).await {
+                    Ok(dl) => Ok((handle, dl, slot)),
+                    Err(e) => {
+                        ctx.report(progress::Event::MapFailed {
+                            category: handle,
+                            map_uid,
+                            error: format!("{e:#}"),
+                        });
+                        Err(e.context(context))
+                    }
+                }
+            }
+        })
+        .buffered(maps_len.max(1))
+}
+
+#[derive(serde::Deserialize)]
+struct SimpleEventEdition {
+    id: u32,
+    name: String,
+}
+
+impl fmt::Display for SimpleEventEdition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Event edition `{}` (Edition ID: {})", self.name, self.id)
+    }
+}
+
+/// Awaits a request for the metadata of the event, with a spinner so that a slow API doesn't
+/// look like a hang.
+async fn fetching<T>(ctx: &Context, fut: impl std::future::Future<Output = T>) -> T {
+    let _spinner = spinner::Spinner::start(ctx.args.quiet, "Fetching edition metadata...");
+    fut.await
+}
+
+/// Fetches the edition from the API, or from its cache file in `cache` when the API can't be
+/// reached.
+async fn fetch_edition(
+    ctx: &Context,
+    event_handle: &str,
+    event_edition: u32,
+    cache: Option<&Path>,
+) -> anyhow::Result<EventEdition> {
+    fetching(
+        ctx,
+        imp::get_event_edition(
+            &ctx.api_client,
+            &ctx.retry,
+            &ctx.bandwidth,
+            ctx.api_host(),
+            event_handle,
+            event_edition,
+            cache,
+        ),
+    )
+    .await
+}
+
+/// Fetches the edition and selects its maps as a download does, checking that their paths
+/// don't collide. Returns the category handle and the UID of the maps dropped by the filters
+/// along with the edition.
+async fn planned_maps(
+    ctx: &Context,
+    event_handle: &str,
+    event_edition: u32,
+    cache: Option<&Path>,
+) -> anyhow::Result<(EventEdition, Vec<(String, String)>)> {
+    let mut event = fetch_edition(ctx, event_handle, event_edition, cache).await?;
+    let filtered_out = select_maps(ctx, &mut event)?;
+    check_collisions(&ctx.args, &event)?;
+    Ok((event, filtered_out))
+}
+
+async fn resolve_edition(
+    ctx: &Context,
+    event_handle: Option<String>,
+    event_edition: Option<u32>,
+) -> anyhow::Result<(String, u32)> {
+    Ok(match (event_handle, event_edition) {
+        (Some(event), Some(edition)) => (event, edition),
+        (Some(event), None) => {
+            log::info!("Provided `{event}` event, querying last edition...");
+            let edition = fetching(
+                ctx,
+                imp::get_last_edition_of(
+                    &ctx.api_client,
+                    &ctx.retry,
+                    &ctx.bandwidth,
+                    ctx.api_host(),
+                    &event,
+                ),
+            )
+            .await?;
+            (event, edition.id)
+        }
+        (None, Some(_)) => {
+            anyhow::bail!("Cannot provide an edition ID without an event handle");
+        }
+        (None, None) => {
+            let event = ctx.args.default_handle.clone();
+            log::info!("No parameter provided, querying last edition of `{event}`...");
+            let last_edition_id = fetching(
+                ctx,
+                imp::get_last_edition_of(
+                    &ctx.api_client,
+                    &ctx.retry,
+                    &ctx.bandwidth,
+                    ctx.api_host(),
+                    &event,
+                ),
+            )
+            .await?
+            .id;
+            (event, last_edition_id)
+        }
+    })
+}
+
+/// Keeps a single occurrence of the maps listed several times in the same category, since they
+/// would be written to the same file.
+fn dedup_category_maps(ctx: &Context, event: &mut EventEdition) -> anyhow::Result<()> {
+    for cat in &mut event.categories {
+        let mut seen = HashSet::new();
+        let mut duplicates = Vec::new();
+        cat.maps.retain(|map| {
+            let first = seen.insert(map.map_uid.clone());
+            if !first {
+                duplicates.push(map.map_uid.clone());
+            }
+            first
+        });
+        if duplicates.is_empty() {
+            continue;
+        }
+        let msg = format!(
+            "Category `{}` lists the same maps several times: {}",
+            cat.handle,
+            duplicates.join(", ")
+        );
+        anyhow::ensure!(!ctx.args.strict, msg);
+        log::warn!("{msg}, downloading them once");
+    }
+    Ok(())
+}
+
+/// Keeps only the maps selected by `--include-map` and `--include-map-glob`, if any.
+fn apply_includes(args: &Command, event: &mut EventEdition) -> anyhow::Result<()> {
+    if args.include_maps.is_empty() && args.include_map_globs.is_empty() {
+        return Ok(());
+    }
+    let mut builder = globset::GlobSetBuilder::new();
+    for glob in &args.include_map_globs {
+        builder.add(glob.clone());
+    }
+    let globs = builder.build().context("Invalid map UID pattern")?;
+    let mut used = vec![false; args.include_map_globs.len()];
+    for cat in &mut event.categories {
+        cat.maps.retain(|map| {
+            let matches = globs.matches(&map.map_uid);
+            for &i in &matches {
+                used[i] = true;
+            }
+            !matches.is_empty() || args.include_maps.contains(&map.map_uid)
+        });
+    }
+    event.categories.retain(|cat| !cat.maps.is_empty());
+    for (glob, used) in args.include_map_globs.iter().zip(used) {
+        if !used {
+            log::warn!(
+                "Pattern `{}` doesn't match any map of the edition",
+                glob.glob()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Sorts the maps of each category following `--sort`, equal maps keep the order of the API.
+fn sort_maps(args: &Command, event: &mut EventEdition) {
+    for cat in &mut event.categories {
+        match args.sort {
+            Sort::Api => (),
+            Sort::Uid => cat.maps.sort_by(|a, b| a.map_uid.cmp(&b.map_uid)),
+            Sort::MxId => cat.maps.sort_by_key(|map| map.mx_id),
+            Sort::Name => cat
+                .maps
+                .sort_by(|a, b| (a.name.is_none(), &a.name).cmp(&(b.name.is_none(), &b.name))),
+        }
+    }
+}
+
+/// Numbers the maps of each category with `--naming index-uid`, before filtering them so that
+/// a map keeps its file name whatever the filters.
+fn number_maps(args: &Command, event: &mut EventEdition) {
+    if !matches!(args.naming, Naming::IndexUid) {
+        return;
+    }
+    for cat in &mut event.categories {
+        let width = cat.maps.len().to_string().len();
+        for (i, map) in cat.maps.iter_mut().enumerate() {
+            map.index = Some((i + 1, width));
+        }
+    }
+}
+
+/// Keeps only the first `limit` maps of the edition, following the order of the categories and
+/// their maps after sorting them.
+fn apply_limit(event: &mut EventEdition, limit: usize) {
+    let total = event
+        .categories
+        .iter()
+        .map(|cat| cat.maps.len())
+        .sum::<usize>();
+    if total <= limit {
+        return;
+    }
+    let mut left = limit;
+    for cat in &mut event.categories {
+        cat.maps.truncate(left);
+        left -= cat.maps.len();
+    }
+    event.categories.retain(|cat| !cat.maps.is_empty());
+    log::warn!("Download limited to {limit} out of {total} maps, the result is partial");
+}
+
+/// Keeps only the first `limit` categories of the edition.
+fn apply_category_limit(event: &mut EventEdition, limit: usize) {
+    let total = event.categories.len();
+    if total <= limit {
+        return;
+    }
+    event.categories.truncate(limit);
+    log::warn!("Download limited to {limit} out of {total} categories, the result is partial");
+}
+
+/// Returns the manifest entry of a map written to `file`, without the details of its download.
+fn manifest_entry(
+    cat_handle: &str,
+    map: &Map,
+    file: PathBuf,
+    size: u64,
+    sha256: Option<String>,
+) -> manifest::Entry {
+    manifest::Entry {
+        category: cat_handle.to_owned(),
+        map_uid: map.map_uid.clone(),
+        mx_id: map.mx_id,
+        mx_version: map.mx_version,
+        index: map.index.map(|(index, _)| index),
+        file,
+        size,
+        sha256,
+        etag: None,
+        provenance: None,
+    }
+}
+
+/// Selects the maps of the edition to download, in their order, following the options. Returns
+/// the category handle and the UID of the maps dropped by the filters.
+fn select_maps(ctx: &Context, event: &mut EventEdition) -> anyhow::Result<Vec<(String, String)>> {
+    let args = &ctx.args;
+    dedup_category_maps(ctx, event)?;
+    sort_maps(args, event);
+    number_maps(args, event);
+    let listed = event
+        .categories
+        .iter()
+        .flat_map(|cat| {
+            cat.maps
+                .iter()
+                .map(move |map| (cat.handle.clone(), map.map_uid.clone()))
+        })
+        .collect::<Vec<_>>();
+    apply_includes(args, event)?;
+    if let Some(limit) = args.limit_categories {
+        apply_category_limit(event, limit);
+    }
+    if let Some(limit) = args.limit {
+        apply_limit(event, limit);
+    }
+    let kept = event
+        .categories
+        .iter()
+        .flat_map(|cat| cat.maps.iter().map(move |map| (&cat.handle, &map.map_uid)))
+        .collect::<HashSet<_>>();
+    Ok(listed
+        .into_iter()
+        .filter(|(cat_handle, map_uid)| !kept.contains(&(cat_handle, map_uid)))
+        .collect())
+}
+
+/// Returns whether MX knows the map at `url`, without downloading it.
+///
+/// A host not supporting `HEAD`, like some mirrors, doesn't tell: the map is then assumed to
+/// exist, its download checks it.
+async fn map_exists(ctx: &Context, url: &str) -> anyhow::Result<bool> {
+    if let Ok(url) = reqwest::Url::parse(url) {
+        check_host(&ctx.args, &url)?;
+    }
+    retry::run(&ctx.retry, || async {
+        let _permit = ctx
+            .limiter
+            .acquire()
+            .await
+            .map_err(|e| retry::Error::Permanent(e.into()))?;
+        ctx.mx_pacer.wait().await;
+        let _in_flight = ctx.mx_in_flight.enter();
+        let req = ctx.client.head(url).header("User-Agent", USER_AGENT);
+        let res = curl::send(with_mx_auth(&ctx.args, url, req)).await;
+        match res {
+            Ok(res)
+                if matches!(
+                    res.status(),
+                    reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE
+                ) =>
+            {
+                Ok(false)
+            }
+            Ok(res)
+                if matches!(
+                    res.status(),
+                    reqwest::StatusCode::METHOD_NOT_ALLOWED | reqwest::StatusCode::NOT_IMPLEMENTED
+                ) =>
+            {
+                log::debug!(
+                    "`{url}` doesn't support HEAD ({}), the map is checked by its download",
+                    res.status()
+                );
+                Ok(true)
+            }
+            res => ctx.retry.check(res).map(|_| true),
+        }
+    })
+    .await
+}
+
+/// Checks that all the maps of the edition exist before downloading any of them, the missing
+/// ones are dropped, or fail the download with `--strict`.
+async fn head_check(ctx: &Context, event: &mut EventEdition) -> anyhow::Result<()> {
+    let maps = event
+        .categories
+        .iter()
+        .flat_map(|cat| cat.maps.iter().map(move |map| (&cat.handle, map)));
+    log::info!("Checking that the maps exist...");
+    let missing = futures::stream::iter(maps)
+        .map(|(cat_handle, map)| async move {
+            let url = map_url(&ctx.args, map)?;
+            let exists = map_exists(ctx, &url)
+                .await
+                .with_context(|| format!("Unable to check map {map} at {url}"))?;
+            anyhow::Ok((!exists).then(|| (cat_handle.clone(), map.to_string())))
+        })
+        .buffer_unordered(ctx.args.concurrency.max(1) as _)
+        .try_filter_map(|missing| async move { Ok(missing) })
+        .try_collect::<HashSet<_>>()
+        .await?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut names = missing
+        .iter()
+        .map(|(cat_handle, map)| format!("{map} in `{cat_handle}`"))
+        .collect::<Vec<_>>();
+    names.sort();
+    let msg = format!(
+        "{} map(s) not found on MX: {}",
+        names.len(),
+        names.join(", ")
+    );
+    anyhow::ensure!(!ctx.args.strict, msg);
+    log::warn!("{msg}, skipping them");
+    for cat in &mut event.categories {
+        cat.maps.retain(|map| {
+            let found = !missing.contains(&(cat.handle.clone(), map.to_string()));
+            if !found {
+                ctx.skips
+                    .add(&cat.handle, &map.map_uid, stats::SkipReason::NotOnMx);
+            }
+            found
+        });
+    }
+    event.categories.retain(|cat| !cat.maps.is_empty());
+    Ok(())
+}
+
+/// Fails if several maps of the edition would be written to the same file.
+fn check_collisions(args: &Command, event: &EventEdition) -> anyhow::Result<()> {
+    let mut paths = HashMap::new();
+    for cat in &event.categories {
+        for map in &cat.maps {
+            let path = map_rel_path(args, &cat.handle, map);
+            match paths.insert(path.clone(), map) {
+                // The same map listed in several categories of a flat layout
+                Some(other) if other.map_uid == map.map_uid && other.mx_id == map.mx_id => (),
+                Some(other) => anyhow::bail!(
+                    "Maps {other} and {map} would both be written to `{}`",
+                    path.display()
+                ),
+                None => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the output roots, `--out` first.
+fn out_roots(args: &Command) -> impl Iterator<Item = &str> {
+    std::iter::once(args.out.as_str()).chain(args.mirrors.iter().map(String::as_str))
+}
+
+/// Returns the directory of the edition in each output root, the one in `--out` first.
+fn edition_dirs(args: &Command, event_handle: &str, event_edition: u32) -> Vec<PathBuf> {
+    out_roots(args)
+        .map(|root| {
+            let dir = PathBuf::from(root).join(event_handle);
+            if args.no_subdir_edition {
+                dir
+            } else {
+                dir.join(event_edition.to_string())
+            }
+        })
+        .collect()
+}
+
+fn create_dir(path: &Path, mode: Option<u32>) -> anyhow::Result<()> {
+    std::fs::create_dir_all(path)
+        .with_context(|| format!("Unable to create directory `{}`", path.display()))?;
+    perms::set_mode(path, mode)
+}
+
+/// Returns the path of the thumbnail of the map written at `path`.
+fn thumbnail_path(
+    path: &Path,
+    map_ext: &str,
+    dl: &DownloadedMap,
+    thumbnail: &Thumbnail,
+) -> PathBuf {
+    path.with_file_name(format!(
+        "{}.{}",
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(map_ext))
+            .unwrap_or(&dl.map.map_uid),
+        thumbnail.extension
+    ))
+}
+
+/// Adds the map and its thumbnail to the archive of the edition.
+async fn archive_map(
+    zip: &archive::Writer,
+    map_ext: &str,
+    dl: &DownloadedMap,
+    rel_path: &Path,
+) -> anyhow::Result<()> {
+    zip.add(rel_path, dl.content.clone()).await?;
+    if let Some(thumbnail) = &dl.thumbnail {
+        zip.add(
+            &thumbnail_path(rel_path, map_ext, dl, thumbnail),
+            thumbnail.content.clone(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Writes the map and its thumbnail at `path`, or links it from the store.
+fn write_map_file(
+    store: Option<&cas::Store>,
+    file_mode: Option<u32>,
+    tempdir: Option<&Path>,
+    map_ext: &str,
+    dl: &DownloadedMap,
+    path: &Path,
+) -> anyhow::Result<()> {
+    match (store, &dl.sha256) {
+        (Some(store), Some(sha256)) => {
+            let object = store.insert(&dl.map, sha256, &dl.content, file_mode)?;
+            cas::link(&object, path)?;
+        }
+        _ => {
+            output::write_file_atomic(path, &dl.content, tempdir)?;
+            perms::set_mode(path, file_mode)?;
+        }
+    }
+    if let Some(thumbnail) = &dl.thumbnail {
+        let path = thumbnail_path(path, map_ext, dl, thumbnail);
+        output::write_file_atomic(&path, &thumbnail.content, tempdir)?;
+        perms::set_mode(&path, file_mode)?;
+    }
+    Ok(())
+}
+
+/// Writes the map at each path on the blocking thread pool, following the `--parallel-writes`
+/// limit.
+async fn write_map(
+    ctx: &Context,
+    dl: DownloadedMap,
+    paths: Vec<PathBuf>,
+) -> anyhow::Result<DownloadedMap> {
+    let _permit = ctx.write_limiter.acquire().await?;
+    let store = ctx.store.clone();
+    let file_mode = ctx.args.file_mode;
+    let tempdir = ctx.args.tempdir.clone();
+    let map_ext = ctx.args.map_ext.clone();
+    tokio::task::spawn_blocking(move || {
+        for path in &paths {
+            write_map_file(
+                store.as_deref(),
+                file_mode,
+                tempdir.as_deref(),
+                &map_ext,
+                &dl,
+                path,
+            )?;
+        }
+        Ok(dl)
+    })
+    .await
+    .context("Write task panicked")?
+}
+
+/// Returns whether the map file exists, was modified after `newer_than` if set, and matches
+/// the size and checksum of its entry in the previous manifest if any.
+async fn is_intact(
+    path: &Path,
+    newer_than: Option<SystemTime>,
+    entry: Option<&manifest::Entry>,
+) -> anyhow::Result<bool> {
+    if !path.is_file() {
+        return Ok(false);
+    }
+    if let Some(newer_than) = newer_than {
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| {
+                format!(
+                    "Unable to read the modification time of `{}`",
+                    path.display()
+                )
+            })?;
+        if modified < newer_than {
+            log::debug!(
+                "`{}` was modified before `--if-newer-than`, downloading it again",
+                path.display()
+            );
+            return Ok(false);
+        }
+    }
+    let Some(entry) = entry else {
+        return Ok(true);
+    };
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("Unable to read the metadata of `{}`", path.display()))?
+        .len();
+    let intact = size == entry.size
+        && match &entry.sha256 {
+            Some(sha256) => {
+                let content = std::fs::read(path)
+                    .with_context(|| format!("Unable to read `{}`", path.display()))?;
+                sha256_of(content.into()).await? == *sha256
+            }
+            None => true,
+        };
+    if !intact {
+        log::warn!(
+            "`{}` doesn't match the manifest, downloading it again",
+            path.display()
+        );
+    }
+    Ok(intact)
+}
+
+/// Returns the maps of the edition if it's already complete in every output root, according to
+/// their manifest.
+fn complete_edition(
+    args: &Command,
+    event_handle: &str,
+    event_edition: u32,
+) -> Option<Vec<manifest::Entry>> {
+    let mut maps = Vec::new();
+    for dir in edition_dirs(args, event_handle, event_edition) {
+        let manifest = manifest::Manifest::read(&dir.join(manifest::FILE_NAME)).ok()?;
+        if manifest.event != event_handle
+            || manifest.edition != event_edition
+            || !manifest.is_fulfilled(&dir)
+        {
+            return None;
+        }
+        maps = manifest.maps;
+    }
+    Some(maps)
+}
+
+async fn process_edition(
+    ctx: &Context,
+    event_handle: &str,
+    event_edition: u32,
+) -> anyhow::Result<stats::Stats> {
+    let start = std::time::Instant::now();
+    let res = edition::download(ctx, event_handle, event_edition).await;
+    ctx.metrics.record(
+        event_handle,
+        event_edition,
+        res.as_ref().ok().copied(),
+        start.elapsed(),
+    );
+    res
+}
+
+/// Returns the maps of the edition to download to `edition_dir`, with their URL.
+fn edition_plan(
+    args: &Command,
+    event_handle: &str,
+    event_edition: u32,
+    event: &EventEdition,
+    edition_dir: &Path,
+) -> anyhow::Result<plan::Plan> {
+    Ok(plan::Plan {
+        event: event_handle.to_owned(),
+        edition: event_edition,
+        maps: event
+            .categories
+            .iter()
+            .flat_map(|cat| {
+                cat.maps.iter().map(|map| {
+                    Ok(plan::Download {
+                        category: cat.handle.clone(),
+                        map_uid: map.map_uid.clone(),
+                        mx_id: map.mx_id,
+                        path: edition_dir.join(map_rel_path(args, &cat.handle, map)),
+                        url: map_url(args, map)?,
+                    })
+                })
+            })
+            .collect::<anyhow::Result<_>>()?,
+    })
+}
+
+/// Runs the `version` subcommand if it's the one of the arguments, and returns whether it did.
+///
+/// It doesn't need a [`Context`], whose creation makes the directories of the output and the
+/// HTTP clients, so that it has no side effects.
+pub fn run_version(args: &Command) -> bool {
+    let version = matches!(args.subcommand, Some(Subcommand::Version));
+    if version {
+        version::run(args);
+    }
+    version
+}
+
+/// Runs the command described by the context's arguments.
+pub async fn run(ctx: &Context) -> anyhow::Result<()> {
+    match &ctx.args.subcommand {
+        Some(Subcommand::Doctor) => return doctor::run(ctx).await,
+        Some(Subcommand::Version) => {
+            version::run(&ctx.args);
+            return Ok(());
+        }
+        Some(Subcommand::PrintUrl {
+            mx_id,
+            event_handle,
+            event_edition,
+        }) => return urls::run(ctx, *mx_id, event_handle.clone(), *event_edition).await,
+        Some(Subcommand::ExportScript {
+            event_handle,
+            event_edition,
+        }) => return script::run(ctx, event_handle.clone(), *event_edition).await,
+        Some(Subcommand::CompareEditions {
+            event_handle,
+            from,
+            to,
+            json,
+        }) => {
+            let json = *json || ctx.args.output_json;
+            return compare::run(ctx, event_handle, *from, *to, json).await;
+        }
+        Some(Subcommand::ListCategories {
+            event_handle,
+            event_edition,
+            json,
+        }) => {
+            let json = *json || ctx.args.output_json;
+            return categories::run(ctx, event_handle.clone(), *event_edition, json).await;
+        }
+        None => (),
+    }
+
+    let report = match ctx.args.follow {
+        Some(interval) => follow::run(ctx, interval).await,
+        None => download(ctx).await,
+    };
+    if let Some(path) = &ctx.args.metrics_file {
+        ctx.metrics.write(path)?;
+    }
+    if let Some(path) = &ctx.args.csv {
+        ctx.index.write(path)?;
+    }
+    let log_failures = || {
+        if let Some(failures) = ctx.failures.summary() {
+            log::error!("Failures by kind: {failures}");
+        }
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            ctx.failures.record(&e);
+            log_failures();
+            return Err(e);
+        }
+    };
+    let skips = report.skips_summary();
+    if ctx.args.summary_only {
+        println!("Done: {report}");
+        if let Some(skips) = &skips {
+            println!("Skipped maps by reason: {skips}");
+        }
+    } else {
+        log::info!("Done: {report}");
+        if let Some(skips) = &skips {
+            log::info!("Skipped maps by reason: {skips}");
+        }
+    }
+    log_failures();
+    if ctx.args.output_json {
+        let json = serde_json::to_string(&report).context("Unable to serialize the report")?;
+        println!("{json}");
+    }
+    anyhow::ensure!(
+        report.failed == 0,
+        "{} edition(s) failed to download",
+        report.failed
+    );
+    Ok(())
+}
+
+/// Downloads the edition or the batch of editions described by the context's arguments.
+pub async fn download(ctx: &Context) -> anyhow::Result<Report> {
+    let start = std::time::Instant::now();
+    let stats = match (&ctx.args.batch, &ctx.args.map_list) {
+        (Some(batch), _) => {
+            probe_roots(ctx)?;
+            batch::run(ctx, batch).await?
+        }
+        (None, Some(map_list)) => {
+            probe_roots(ctx)?;
+            maplist::run(ctx, map_list).await?
+        }
+        (None, None) if ctx.args.refresh_latest => return refresh_latest(ctx).await,
+        (None, None) if ctx.args.since_edition.is_some() => {
+            probe_roots(ctx)?;
+            download_since(ctx).await?
+        }
+        (None, None) => {
+            let (event_handle, event_edition) =
+                resolve_edition(ctx, ctx.args.event_handle.clone(), ctx.args.event_edition).await?;
+            return download_edition(ctx, &event_handle, Some(event_edition)).await;
+        }
+    };
+    Ok(Report::new(
+        stats,
+        start.elapsed(),
+        ctx.mx_in_flight.peak(),
+        ctx.memory.peak(),
+        ctx.bandwidth.per_host(),
+        ctx.skips.list(),
+    ))
+}
+
+/// Downloads the last edition of the event if it's newer than the one recorded by the previous
+/// run.
+async fn refresh_latest(ctx: &Context) -> anyhow::Result<Report> {
+    let start = std::time::Instant::now();
+    let (event_handle, event_edition) =
+        resolve_edition(ctx, ctx.args.event_handle.clone(), None).await?;
+    let path = cache::latest_path(&ctx.args.out, &event_handle);
+    let downloaded =
+        if path.is_file() {
+            let content = cache::load(&path)?;
+            Some(content.trim().parse::<u32>().with_context(|| {
+                format!("Invalid last downloaded edition in `{}`", path.display())
+            })?)
+        } else {
+            None
+        };
+
+    match downloaded {
+        Some(downloaded) if downloaded >= event_edition => {
+            log::info!(
+                "No update needed, edition {event_edition} of `{event_handle}` is the last one and \
+                 was already downloaded"
+            );
+            return Ok(Report::new(
+                stats::Stats::default(),
+                start.elapsed(),
+                ctx.mx_in_flight.peak(),
+                ctx.memory.peak(),
+                ctx.bandwidth.per_host(),
+                ctx.skips.list(),
+            ));
+        }
+        Some(downloaded) => log::info!(
+            "Update needed, edition {event_edition} of `{event_handle}` is newer than the last \
+             downloaded edition {downloaded}"
+        ),
+        None => log::info!(
+            "Update needed, no edition of `{event_handle}` was downloaded with \
+             `--refresh-latest` yet"
+        ),
+    }
+
+    let report = download_edition(ctx, &event_handle, Some(event_edition)).await?;
+    if report.failed == 0 && ctx.args.dry_run.is_none() {
+        cache::store(&path, &format!("{event_edition}\n"))?;
+    }
+    Ok(report)
+}
+
+/// Downloads the editions of the event newer than `--since-edition`, oldest first.
+///
+/// The failed editions don't stop the others, they're counted in the stats.
+async fn download_since(ctx: &Context) -> anyhow::Result<stats::Stats> {
+    let since = ctx.args.since_edition.unwrap_or_default();
+    let event_handle = ctx
+        .args
+        .event_handle
+        .clone()
+        .unwrap_or_else(|| ctx.args.default_handle.clone());
+    let mut editions = fetching(
+        ctx,
+        imp::get_editions_of(
+            &ctx.api_client,
+            &ctx.retry,
+            &ctx.bandwidth,
+            ctx.api_host(),
+            &event_handle,
+        ),
+    )
+    .await?
+    .into_iter()
+    .map(|edition| edition.id)
+    .filter(|&id| id > since)
+    .collect::<Vec<_>>();
+    editions.sort_unstable();
+    log::info!(
+        "{} edition(s) of `{event_handle}` after edition {since}",
+        editions.len()
+    );
+
+    let mut total = stats::Stats::default();
+    let mut downloaded = Vec::new();
+    for edition in editions {
+        match process_edition(ctx, &event_handle, edition).await {
+            Ok(stats) => {
+                log::info!("Edition {edition} of `{event_handle}`: {stats}");
+                if stats.maps > 0 {
+                    downloaded.push(edition.to_string());
+                }
+                total += stats;
+            }
+            Err(e) => {
+                ctx.failures.record(&e);
+                log::error!("Edition {edition} of `{event_handle}` failed: {e:#}");
+                total.failed += 1;
+            }
+        }
+    }
+    if downloaded.is_empty() {
+        log::info!("No new map in the editions of `{event_handle}` after edition {since}");
+    } else {
+        log::info!(
+            "New maps downloaded from edition(s) {} of `{event_handle}`",
+            downloaded.join(", ")
+        );
+    }
+    Ok(total)
+}
+
+/// Downloads an edition of an event, or its last edition if none is provided.
+///
+/// The other options, like the output directory, are the ones of the context.
+pub async fn download_edition(
+    ctx: &Context,
+    event_handle: &str,
+    event_edition: Option<u32>,
+) -> anyhow::Result<Report> {
+    let start = std::time::Instant::now();
+    probe_roots(ctx)?;
+    let (event_handle, event_edition) =
+        resolve_edition(ctx, Some(event_handle.to_owned()), event_edition).await?;
+    let stats = process_edition(ctx, &event_handle, event_edition).await?;
+    Ok(Report::new(
+        stats,
+        start.elapsed(),
+        ctx.mx_in_flight.peak(),
+        ctx.memory.peak(),
+        ctx.bandwidth.per_host(),
+        ctx.skips.list(),
+    ))
+}
+
+fn probe_roots(ctx: &Context) -> anyhow::Result<()> {
+    if ctx.args.dry_run.is_none() {
+        for root in out_roots(&ctx.args) {
+            output::probe_writable(Path::new(root))?;
+        }
+        if let Some(tempdir) = &ctx.args.tempdir {
+            output::probe_writable(tempdir)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_ignores_environment() {
+        std::env::set_var("SOEVENT_DEFAULT_HANDLE", "from-env");
+        let args = Command::default();
+        std::env::remove_var("SOEVENT_DEFAULT_HANDLE");
+        assert_eq!(args.default_handle, "campaign");
+    }
+
+    #[test]
+    fn mirror_auth() {
+        let parse = |args: &[&str]| {
+            <Command as clap::Parser>::try_parse_from(["soevent"].iter().chain(args))
+        };
+        assert!(parse(&["--mx-user", "me"]).is_err());
+        let template = "https://mirror.example.com/{mx_id}";
+        let args = parse(&["--mx-user", "me", "--map-url-template", template]).unwrap();
+        assert_eq!(args.mx_user.as_deref(), Some("me"));
+        let fallback = "https://mirror.example.com";
+        let args = parse(&["--mx-user", "me", "--mx-fallback", fallback]).unwrap();
+        assert_eq!(
+            mx_auth_user(&args, "https://mirror.example.com/maps/download/1"),
+            Some("me")
+        );
+        assert_eq!(
+            mx_auth_user(&args, &format!("{MX_HOST}/maps/download/1")),
+            None
+        );
+        // Without the user, the password is ignored instead of rejected
+        let args = parse(&["--mx-pass", "secret"]).unwrap();
+        assert!(args.mx_user.is_none());
+        assert_eq!(mx_auth_user(&args, "https://mirror.example.com/1"), None);
+    }
+
+    #[tokio::test]
+    async fn sums_line_format() {
+        let sha256 = sha256_of(bytes::Bytes::from("abc")).await.unwrap();
+        assert_eq!(
+            sums_line(&sha256, &Path::new("white").join("uidA.Map.Gbx")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  \
+             white/uidA.Map.Gbx\n"
+        );
+    }
+
+    #[test]
+    fn map_file_names() {
+        assert!(is_map_file_name(".Map.Gbx", "Café Run.Map.Gbx"));
+        assert!(is_map_file_name(".Map.Gbx", "run.map.gbx"));
+        assert!(is_map_file_name(".Map.Gbx", "run.Gbx"));
+        assert!(is_map_file_name(".gbx.bak", "run.gbx.bak"));
+        assert!(!is_map_file_name(".Map.Gbx", ".gbx"));
+        assert!(!is_map_file_name(".Map.Gbx", "manifest.json"));
+        assert!(!is_map_file_name(".Map.Gbx", "SHA256SUMS"));
+        assert!(!is_map_file_name(".Map.Gbx", "run.Map.Gbx.jpg"));
+    }
+
+    #[test]
+    fn expand_env_literals() {
+        assert_eq!(expand_env("/tmp/a%20b%c").unwrap(), "/tmp/a%20b%c");
+        assert_eq!(expand_env("/tmp/$1x").unwrap(), "/tmp/$1x");
+        assert_eq!(expand_env("/tmp/$$HOME").unwrap(), "/tmp/$HOME");
+        assert_eq!(expand_env("/tmp/$").unwrap(), "/tmp/$");
+    }
+
+    #[test]
+    fn expand_env_variables() {
+        std::env::set_var("SOEVENT_TEST_DIR", "maps");
+        assert_eq!(
+            expand_env("/tmp/$SOEVENT_TEST_DIR/x").unwrap(),
+            "/tmp/maps/x"
+        );
+        assert_eq!(
+            expand_env("/tmp/${SOEVENT_TEST_DIR}x").unwrap(),
+            "/tmp/mapsx"
+        );
+        assert!(expand_env("/tmp/$SOEVENT_TEST_UNSET").is_err());
+    }
+}
+
+#[cfg(all(test, feature = "localhost_test"))]
+mod replay_tests {
+    use super::*;
+
+    /// Replays the download of `campaign` recorded with `--record` against a local API, the
+    /// maps coming from `--map-url-template http://localhost:3001/maps/download/{mx_id}`, and
+    /// checks the UID of the map files and their manifest entries
+    async fn replay(test: &str, extra_args: &[&str], files: &[(&str, &str)]) {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/campaign");
+        let out = std::env::temp_dir().join(format!("soevent-{test}-{}", std::process::id()));
+        let args = <Command as clap::Parser>::try_parse_from(
+            [
+                "soevent",
+                "campaign",
+                "--out",
+                out.to_str().unwrap(),
+                "--replay",
+                fixtures.to_str().unwrap(),
+                "--map-url-template",
+                "http://localhost:3001/maps/download/{mx_id}",
+                "--manifest",
+            ]
+            .iter()
+            .chain(extra_args),
+        )
+        .unwrap();
+        let ctx = Context::new(args).unwrap();
+        download(&ctx).await.unwrap();
+
+        let dir = out.join("campaign").join("2");
+        for (file, uid) in files {
+            let content = std::fs::read(dir.join(file)).unwrap();
+            assert_eq!(gbx::map_uid(&content).unwrap(), *uid);
+        }
+        let manifest = manifest::Manifest::read(&dir.join(manifest::FILE_NAME)).unwrap();
+        assert!(manifest.complete);
+        let mut listed = manifest
+            .maps
+            .iter()
+            .map(|entry| (entry.file.clone(), entry.map_uid.as_str()))
+            .collect::<Vec<_>>();
+        listed.sort();
+        let mut expected = files
+            .iter()
+            .map(|&(file, uid)| (PathBuf::from(file), uid))
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(listed, expected);
+        std::fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replays_download() {
+        let files = [
+            ("white/uidA.Map.Gbx", "uidA"),
+            ("white/uidB.Map.Gbx", "uidB"),
+            ("green/uidC.Map.Gbx", "uidC"),
+        ];
+        replay("replay", &[], &files).await;
+    }
+
+    /// The fixtures send the same name for `uidA` and `uidB`, and a path for `uidC`
+    #[tokio::test]
+    async fn replays_mx_names() {
+        let files = [
+            ("white/Café Run.Map.Gbx", "uidA"),
+            ("white/uidB.Map.Gbx", "uidB"),
+            ("green/x;y.Map.Gbx", "uidC"),
+        ];
+        replay("replay-mx", &["--naming", "mx"], &files).await;
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+
+pub const FILE_NAME: &str = "manifest.json";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub event: String,
+    pub edition: u32,
+    pub name: String,
+    /// Whether the run which wrote the manifest downloaded every map of the edition, it's not
+    /// the case of the partial manifests written after each category.
+    #[serde(default)]
+    pub complete: bool,
+    pub maps: Vec<Entry>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    pub category: String,
+    pub map_uid: String,
+    pub mx_id: crate::MxId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mx_version: Option<u32>,
+    /// The position of the map in its category with `--naming index-uid`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// The path of the map file, relative to the edition directory.
+    pub file: PathBuf,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Sent back to MX with `--conditional`, to download the map only if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+/// Where the bytes of a map come from, recorded with `--provenance`.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Provenance {
+    pub url: String,
+    pub status: u16,
+    pub final_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// Omitted with `--reproducible`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloaded_at: Option<String>,
+}
+
+impl Provenance {
+    pub fn new(url: &str, res: &reqwest::Response, with_time: bool) -> Self {
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        Self {
+            url: url.to_owned(),
+            status: res.status().as_u16(),
+            final_url: res.url().to_string(),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+            downloaded_at: with_time.then(|| {
+                humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string()
+            }),
+        }
+    }
+}
+
+/// Same as [`Manifest`], without owning its content.
+#[derive(serde::Serialize)]
+struct ManifestRef<'a> {
+    event: &'a str,
+    edition: u32,
+    name: &'a str,
+    complete: bool,
+    maps: &'a [Entry],
+}
+
+impl Manifest {
+    /// Returns whether the manifest is complete and the files of all its maps are in the edition
+    /// directory, with their recorded size.
+    pub fn is_fulfilled(&self, edition_dir: &Path) -> bool {
+        self.complete
+            && self.maps.iter().all(|entry| {
+                std::fs::metadata(edition_dir.join(&entry.file))
+                    .is_ok_and(|meta| meta.is_file() && meta.len() == entry.size)
+            })
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read(path)
+            .with_context(|| format!("Unable to read manifest `{}`", path.display()))?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("Invalid manifest `{}`", path.display()))
+    }
+
+    pub fn write(
+        edition_dir: &Path,
+        event: &str,
+        edition: u32,
+        name: &str,
+        complete: bool,
+        maps: &[Entry],
+    ) -> anyhow::Result<PathBuf> {
+        let path = edition_dir.join(FILE_NAME);
+        let manifest = ManifestRef {
+            event,
+            edition,
+            name,
+            complete,
+            maps,
+        };
+        let content =
+            serde_json::to_vec_pretty(&manifest).context("Unable to serialize manifest")?;
+        crate::output::write_file(&path, content)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+
+use futures::StreamExt as _;
+
+use crate::stats::{SkipReason, Stats};
+use crate::{log, progress, Context, Map};
+
+struct Entry {
+    line: usize,
+    map: Map,
+    category: Option<String>,
+}
+
+/// Parses a list of `<map_uid> <mx_id> [category]` lines.
+fn parse(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read map list `{}`", path.display()))?;
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line, content)| {
+            let mut words = content.split_whitespace();
+            let map_uid = words.next().unwrap_or_default().to_owned();
+            let mx_id = words
+                .next()
+                .with_context(|| format!("Missing MX ID at line {line}"))?
+                .parse()
+                .with_context(|| format!("Invalid MX ID at line {line}"))?;
+            let category = words.next().map(str::to_owned);
+            anyhow::ensure!(
+                words.next().is_none(),
+                "Unexpected trailing content at line {line}"
+            );
+            Ok(Entry {
+                line,
+                map: Map {
+                    mx_id,
+                    map_uid,
+                    mx_version: None,
+                    download_url: None,
+                    sha256: None,
+                    name: None,
+                    index: None,
+                    mx_file_name: None,
+                },
+                category,
+            })
+        })
+        .collect()
+}
+
+/// Returns the path of the map relative to the output roots, in the directory of its category
+/// if it has one.
+fn rel_path(ctx: &Context, entry: &Entry) -> PathBuf {
+    match &entry.category {
+        Some(category) => crate::map_rel_path(&ctx.args, category, &entry.map),
+        None => PathBuf::from(crate::map_file_name(&ctx.args, &entry.map)),
+    }
+}
+
+async fn download(ctx: &Context, entry: Entry) -> anyhow::Result<(PathBuf, Option<u64>)> {
+    let rel_path = rel_path(ctx, &entry);
+    let paths = crate::out_roots(&ctx.args)
+        .map(|root| Path::new(root).join(&rel_path))
+        .collect::<Vec<_>>();
+    if ctx.args.skip_existing && paths.iter().all(|path| path.is_file()) {
+        ctx.skips.add(
+            entry.category.as_deref().unwrap_or_default(),
+            &entry.map.map_uid,
+            SkipReason::AlreadyPresent,
+        );
+        return Ok((rel_path, None));
+    }
+    for path in &paths {
+        if let Some(parent) = path.parent() {
+            crate::create_dir(parent, ctx.args.dir_mode)?;
+        }
+    }
+
+    let category = entry.category.unwrap_or_default();
+    let dl = crate::download_map(ctx, &category, entry.map, None).await?;
+    let dl = crate::write_map(ctx, dl, paths).await?;
+    let size = dl.content.len() as u64;
+    ctx.report(progress::Event::MapFinished {
+        category,
+        map_uid: dl.map.map_uid,
+        size,
+    });
+    Ok((rel_path, Some(size)))
+}
+
+/// Downloads the maps listed in a file, whatever their event.
+pub async fn run(ctx: &Context, path: &Path) -> anyhow::Result<Stats> {
+    let entries = parse(path)?;
+    log::info!("Downloading {} listed map(s)...", entries.len());
+
+    let len = entries.len();
+    let results = futures::stream::iter(entries)
+        .map(|entry| async move {
+            let line = entry.line;
+            let name = entry.map.to_string();
+            let category = entry.category.clone().unwrap_or_default();
+            let map_uid = entry.map.map_uid.clone();
+            let res = download(ctx, entry).await;
+            if let Err(e) = &res {
+                ctx.report(progress::Event::MapFailed {
+                    category,
+                    map_uid,
+                    error: format!("{e:#}"),
+                });
+            }
+            (line, name, res)
+        })
+        .buffered(len.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut stats = Stats::default();
+    for (line, name, res) in results {
+        match res {
+            Ok((rel_path, None)) => {
+                stats.skipped += 1;
+                log::info!(
+                    "[skip] line {line}: {name}, `{}` exists",
+                    rel_path.display()
+                );
+            }
+            Ok((rel_path, Some(size))) => {
+                stats.add_map(size as _);
+                log::info!("[ok]   line {line}: {name} -> `{}`", rel_path.display());
+            }
+            Err(e) => {
+                stats.failed += 1;
+                ctx.failures.record(&e);
+                log::error!("[fail] line {line}: {name}: {e:#}");
+            }
+        }
+    }
+    if stats.failed > 0 {
+        log::error!("{} out of {len} listed maps failed", stats.failed);
+    }
+    Ok(stats)
+}
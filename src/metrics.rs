@@ -0,0 +1,112 @@
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context as _;
+
+use crate::stats::Stats;
+
+struct Edition {
+    event: String,
+    edition: u32,
+    /// `None` if the edition failed to download
+    stats: Option<Stats>,
+    duration: Duration,
+}
+
+/// The outcome of each processed edition, written with `--metrics-file`.
+#[derive(Default)]
+pub struct Metrics(Mutex<Vec<Edition>>);
+
+/// Escapes a label value of the Prometheus text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&Edition) -> f64,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "maps_downloaded_total",
+        kind: "counter",
+        help: "Maps downloaded",
+        value: |e| e.stats.map_or(0., |s| s.maps as _),
+    },
+    Metric {
+        name: "maps_skipped_total",
+        kind: "counter",
+        help: "Maps skipped",
+        value: |e| e.stats.map_or(0., |s| s.skipped as _),
+    },
+    Metric {
+        name: "bytes_downloaded_total",
+        kind: "counter",
+        help: "Bytes of the downloaded maps",
+        value: |e| e.stats.map_or(0., |s| s.bytes as _),
+    },
+    Metric {
+        name: "failures_total",
+        kind: "counter",
+        help: "Failed downloads of the edition",
+        value: |e| if e.stats.is_none() { 1. } else { 0. },
+    },
+    Metric {
+        name: "duration_seconds",
+        kind: "gauge",
+        help: "Time spent on the edition",
+        value: |e| e.duration.as_secs_f64(),
+    },
+];
+
+impl Metrics {
+    pub fn record(&self, event: &str, edition: u32, stats: Option<Stats>, duration: Duration) {
+        self.0.lock().unwrap().push(Edition {
+            event: event.to_owned(),
+            edition,
+            stats,
+            duration,
+        });
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    fn render(&self) -> String {
+        let editions = self.0.lock().unwrap();
+        let mut out = String::new();
+        for Metric {
+            name,
+            kind,
+            help,
+            value,
+        } in METRICS
+        {
+            let _ = writeln!(out, "# HELP soevent_{name} {help}.");
+            let _ = writeln!(out, "# TYPE soevent_{name} {kind}");
+            for e in editions.iter() {
+                let _ = writeln!(
+                    out,
+                    "soevent_{name}{{event=\"{}\",edition=\"{}\"}} {}",
+                    escape(&e.event),
+                    e.edition,
+                    value(e)
+                );
+            }
+        }
+        out
+    }
+
+    /// Writes the metrics to a temporary file renamed to `path`, so that a collector never reads
+    /// a partial file.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        crate::output::write_file_atomic(path, self.render(), None)
+            .with_context(|| format!("Unable to write metrics file `{}`", path.display()))
+    }
+}
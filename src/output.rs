@@ -0,0 +1,104 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Context as _;
+
+/// Writing a file failed because the filesystem is out of space.
+#[derive(Debug)]
+pub struct DiskFull {
+    pub path: PathBuf,
+    source: io::Error,
+}
+
+impl fmt::Display for DiskFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Disk full while writing `{}`", self.path.display())
+    }
+}
+
+impl std::error::Error for DiskFull {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn is_out_of_space(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+    )
+}
+
+fn write_error(path: &Path, e: io::Error) -> anyhow::Error {
+    if is_out_of_space(&e) {
+        DiskFull {
+            path: path.to_owned(),
+            source: e,
+        }
+        .into()
+    } else {
+        anyhow::Error::new(e).context(format!("Unable to write file `{}`", path.display()))
+    }
+}
+
+pub fn write_file<C: AsRef<[u8]>>(path: &Path, content: C) -> anyhow::Result<()> {
+    std::fs::write(path, content).map_err(|e| write_error(path, e))
+}
+
+/// Writes a temporary file renamed to `path`, so that an interrupted run doesn't leave a
+/// truncated file behind.
+///
+/// The temporary file is created in `tempdir`, or next to `path` by default. A file can't be
+/// renamed to another filesystem, it's then copied next to `path` before the rename, which
+/// writes it twice.
+pub fn write_file_atomic<C: AsRef<[u8]>>(
+    path: &Path,
+    content: C,
+    tempdir: Option<&Path>,
+) -> anyhow::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let tmp_name = format!(
+        ".{}.{}-{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let tmp = tempdir.unwrap_or(dir).join(&tmp_name);
+    if let Err(e) = write_file(&tmp, content) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    let res = match std::fs::rename(&tmp, path) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let local = dir.join(&tmp_name);
+            let res = std::fs::copy(&tmp, &local).and_then(|_| std::fs::rename(&local, path));
+            if res.is_err() {
+                let _ = std::fs::remove_file(&local);
+            }
+            let _ = std::fs::remove_file(&tmp);
+            res
+        }
+        res => res,
+    };
+    res.map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        write_error(path, e)
+    })
+}
+
+/// Checks that files can be created in `dir`, creating it if needed, so that a run doesn't
+/// download everything only to fail on the first write.
+pub fn probe_writable(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create output directory `{}`", dir.display()))?;
+    let probe = dir.join(format!(".soevent-probe-{}", std::process::id()));
+    write_file(&probe, b"")
+        .with_context(|| format!("Output directory `{}` isn't writable", dir.display()))?;
+    std::fs::remove_file(&probe)
+        .with_context(|| format!("Unable to remove probe file `{}`", probe.display()))
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Enforces a minimum gap between consecutive requests, whatever task sends them.
+pub struct Pacer {
+    min_delay: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    pub fn new(min_delay: Duration) -> Self {
+        Self {
+            min_delay,
+            next: Mutex::new(None),
+        }
+    }
+
+    pub async fn wait(&self) {
+        if self.min_delay.is_zero() {
+            return;
+        }
+        // The lock is held while sleeping so that the waiters go through one at a time
+        let mut next = self.next.lock().await;
+        if let Some(next) = *next {
+            tokio::time::sleep_until(next).await;
+        }
+        *next = Some(Instant::now() + self.min_delay);
+    }
+}
@@ -0,0 +1,27 @@
+use std::path::Path;
+
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("`{s}` is not a valid octal mode")),
+    }
+}
+
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: Option<u32>) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+    use std::os::unix::fs::PermissionsExt as _;
+
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Unable to set permissions of `{}`", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+#[inline(always)]
+pub fn set_mode(_: &Path, _: Option<u32>) -> anyhow::Result<()> {
+    Ok(())
+}
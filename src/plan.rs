@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+
+use crate::curl::quote;
+
+/// The maps a run would download, printed by `--dry-run`.
+#[derive(serde::Serialize)]
+pub struct Plan {
+    pub event: String,
+    pub edition: u32,
+    pub maps: Vec<Download>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Download {
+    pub category: String,
+    pub map_uid: String,
+    pub mx_id: crate::MxId,
+    pub path: PathBuf,
+    pub url: String,
+}
+
+impl Plan {
+    pub fn print_text(&self) {
+        println!(
+            "Edition {} of `{}`: {} map(s) to download",
+            self.edition,
+            self.event,
+            self.maps.len()
+        );
+        for map in &self.maps {
+            println!(
+                "  {}/{} (MX ID: {}) -> {}",
+                map.category,
+                map.map_uid,
+                map.mx_id,
+                map.path.display()
+            );
+        }
+    }
+
+    /// Returns a shell script downloading the maps with `curl`, authenticating the requests to
+    /// the URLs `user` returns a user for with the password in `SOEVENT_MX_PASS`.
+    pub fn to_script<'a>(
+        &self,
+        user_agent: &str,
+        user: impl Fn(&str) -> Option<&'a str>,
+    ) -> String {
+        let mut script = String::from("#!/bin/sh\n");
+        script += &format!(
+            "# Downloads the {} map(s) of edition {} of `{}`, exported by soevent\n",
+            self.maps.len(),
+            self.edition,
+            self.event
+        );
+        script += "set -eu\n";
+        let mut dirs = Vec::new();
+        for map in &self.maps {
+            if let Some(dir) = map.path.parent().filter(|dir| !dirs.contains(dir)) {
+                dirs.push(dir);
+            }
+        }
+        for dir in dirs {
+            script += &format!("mkdir -p {}\n", quote(&dir.to_string_lossy()));
+        }
+        for map in &self.maps {
+            script += &format!(
+                "curl --fail --silent --show-error --location --retry 3 -H {}",
+                quote(&format!("User-Agent: {user_agent}"))
+            );
+            if let Some(user) = user(&map.url) {
+                script += &format!(" --user {}:\"$SOEVENT_MX_PASS\"", quote(user));
+            }
+            script += &format!(
+                " -o {} {}\n",
+                quote(&map.path.to_string_lossy()),
+                quote(&map.url)
+            );
+        }
+        script
+    }
+
+    /// Prints the plan as JSON on a single line, so that a batch prints one plan per line.
+    pub fn print_json(&self) -> anyhow::Result<()> {
+        let json = serde_json::to_string(self).context("Unable to serialize the plan")?;
+        println!("{json}");
+        Ok(())
+    }
+}
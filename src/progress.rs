@@ -0,0 +1,60 @@
+//! Progress reporting for embedders rendering their own progress UI.
+
+use std::io::Write as _;
+
+/// A significant step of a download.
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    MapStarted {
+        category: String,
+        map_uid: String,
+    },
+    MapFinished {
+        category: String,
+        map_uid: String,
+        size: u64,
+    },
+    MapFailed {
+        category: String,
+        map_uid: String,
+        error: String,
+    },
+    CategoryCompleted {
+        category: String,
+        maps: usize,
+    },
+}
+
+/// Receives the progress events of the downloads.
+///
+/// The events are reported from the download tasks, so the implementation should return quickly.
+pub trait Reporter: Send + Sync {
+    fn report(&self, event: Event);
+}
+
+impl<F: Fn(Event) + Send + Sync> Reporter for F {
+    fn report(&self, event: Event) {
+        self(event)
+    }
+}
+
+/// Prints each event as a line of JSON on stdout, with `--events-jsonl`.
+pub struct JsonLines;
+
+impl Reporter for JsonLines {
+    fn report(&self, event: Event) {
+        if let Ok(json) = serde_json::to_string(&event) {
+            // Without the lock, the lines of concurrent downloads could be interleaved
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{json}").and_then(|()| stdout.flush());
+        }
+    }
+}
+
+/// Forwards the events to an async channel, dropping them if the receiver is gone.
+impl Reporter for tokio::sync::mpsc::UnboundedSender<Event> {
+    fn report(&self, event: Event) {
+        let _ = self.send(event);
+    }
+}
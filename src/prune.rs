@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+
+use crate::log;
+
+fn is_map_file(path: &Path, map_ext: &str) -> bool {
+    path.is_file()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(map_ext))
+}
+
+/// Removes the map files of the edition directory and its category directories that aren't
+/// part of `keep`.
+///
+/// The map files are the files ending with `map_ext`, and `keep` contains their paths relative
+/// to the edition directory.
+pub fn run(edition_dir: &Path, map_ext: &str, keep: &HashSet<PathBuf>) -> anyhow::Result<usize> {
+    let mut removed = 0;
+    let mut dirs = vec![edition_dir.to_owned()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("Unable to read directory `{}`", dir.display()))?;
+        for entry in entries {
+            let path = entry.context("Unable to read directory entry")?.path();
+            if path.is_dir() {
+                // Only look one level down, in the category directories
+                if dir == edition_dir {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            let rel_path = path.strip_prefix(edition_dir).unwrap_or(&path);
+            if is_map_file(&path, map_ext) && !keep.contains(rel_path) {
+                log::info!("Pruning stale map `{}`", path.display());
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Unable to remove `{}`", path.display()))?;
+                removed += 1;
+            }
+        }
+    }
+
+    Ok(removed)
+}
@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::time::Duration;
+
+use crate::log;
+
+#[derive(Clone)]
+pub struct Policy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+    /// Whether a response which can't be parsed is requested again, a proxy may have truncated it.
+    pub parse_errors: bool,
+    /// The failed statuses worth retrying, 429 and the 5xx by default.
+    pub statuses: Option<Vec<reqwest::StatusCode>>,
+}
+
+impl Policy {
+    /// Returns the delay to wait before the retry following the given 0-based attempt.
+    ///
+    /// The backoff is exponential: `min(max_delay, base_delay * 2^attempt)`. With jitter, the
+    /// actual delay is picked uniformly between zero and that value ("full jitter"), so that
+    /// clients failing at the same time don't retry at the same time.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        if self.jitter {
+            delay.mul_f64(fastrand::f64())
+        } else {
+            delay
+        }
+    }
+
+    fn retries_status(&self, status: reqwest::StatusCode) -> bool {
+        match &self.statuses {
+            Some(statuses) => statuses.contains(&status),
+            None => status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Classifies the outcome of a sent request.
+    ///
+    /// Connection errors and timeouts are worth retrying, as well as the failed statuses of the
+    /// policy, other failed statuses won't get better by asking again.
+    pub fn check(
+        &self,
+        res: reqwest::Result<reqwest::Response>,
+    ) -> Result<reqwest::Response, Error> {
+        let res = res.map_err(Error::transient)?;
+        let status = res.status();
+        log::debug!("{} answered {status}", res.url());
+        match res.error_for_status() {
+            Ok(res) => Ok(res),
+            Err(e) if self.retries_status(status) => Err(Error::Transient(e.into())),
+            Err(e) => Err(Error::Permanent(e.into())),
+        }
+    }
+}
+
+pub enum Error {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+impl Error {
+    pub fn transient<E: Into<anyhow::Error>>(e: E) -> Self {
+        Self::Transient(e.into())
+    }
+}
+
+/// Returns the HTTP status of a failed request, if the server answered.
+pub fn http_status(e: &anyhow::Error) -> Option<reqwest::StatusCode> {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+}
+
+/// Describes why a request failed, for the logs.
+fn reason(e: &anyhow::Error) -> String {
+    if let Some(status) = http_status(e) {
+        return format!("HTTP {status}");
+    }
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(e) if e.is_timeout() => "timeout".to_owned(),
+        Some(e) if e.is_connect() => "connection error".to_owned(),
+        Some(e) if e.is_body() || e.is_decode() => "interrupted response".to_owned(),
+        _ if e.is::<serde_json::Error>() => "invalid JSON".to_owned(),
+        _ => "error".to_owned(),
+    }
+}
+
+pub async fn run<T, F, Fut>(policy: &Policy, mut f: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let attempts = policy.max_retries.saturating_add(1);
+    let mut attempt = 0;
+    loop {
+        if attempt > 0 {
+            log::info!("Attempt {}/{attempts}...", attempt + 1);
+        }
+        match f().await {
+            Ok(out) => return Ok(out),
+            Err(Error::Permanent(e)) => return Err(e),
+            Err(Error::Transient(e)) if attempt >= policy.max_retries => return Err(e),
+            Err(Error::Transient(e)) => {
+                let delay = policy.delay(attempt);
+                log::warn!(
+                    "Attempt {}/{attempts} failed ({}), retrying in {delay:.1?}: {e:#}",
+                    attempt + 1,
+                    reason(&e)
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{
+    edition_dirs, edition_plan, mx_auth_user, planned_maps, resolve_edition, Context, USER_AGENT,
+};
+
+/// Prints a shell script downloading the maps of the edition with `curl`, selected and named as
+/// they would be by a download.
+pub async fn run(
+    ctx: &Context,
+    event_handle: Option<String>,
+    event_edition: Option<u32>,
+) -> anyhow::Result<()> {
+    let args = &ctx.args;
+    let (event_handle, event_edition) = resolve_edition(ctx, event_handle, event_edition).await?;
+    let (event, _) = planned_maps(ctx, &event_handle, event_edition, None).await?;
+    let edition_dir = &edition_dirs(args, &event_handle, event_edition)[0];
+    let plan = edition_plan(args, &event_handle, event_edition, &event, edition_dir)?;
+    print!(
+        "{}",
+        plan.to_script(USER_AGENT, |url| mx_auth_user(args, url))
+    );
+    Ok(())
+}
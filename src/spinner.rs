@@ -0,0 +1,50 @@
+use std::io::{self, IsTerminal as _};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The spinner shown, hidden by [`LogWriter`] while printing a log.
+static SHOWN: Mutex<Option<indicatif::ProgressBar>> = Mutex::new(None);
+
+/// A spinner on stderr while the metadata of the event is fetched, cleared when dropped.
+pub struct Spinner(Option<indicatif::ProgressBar>);
+
+impl Spinner {
+    /// Starts the spinner, unless stderr isn't a terminal or the logs are quieted.
+    pub fn start(quiet: bool, message: &'static str) -> Self {
+        if quiet || !io::stderr().is_terminal() {
+            return Self(None);
+        }
+        let bar = indicatif::ProgressBar::new_spinner().with_message(message);
+        bar.enable_steady_tick(Duration::from_millis(100));
+        *SHOWN.lock().unwrap() = Some(bar.clone());
+        Self(Some(bar))
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.0 {
+            SHOWN.lock().unwrap().take();
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Writes the logs to stderr, hiding the spinner while writing so that they don't end up on
+/// its line.
+pub struct LogWriter;
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let shown = SHOWN.lock().unwrap().clone();
+        match shown {
+            Some(bar) => bar.suspend(|| io::stderr().write_all(buf))?,
+            None => io::stderr().write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
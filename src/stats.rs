@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default, Clone, Copy)]
+pub struct Stats {
+    pub maps: usize,
+    pub bytes: u64,
+    pub skipped: usize,
+    /// Number of editions, or of maps of a map list, which failed to download.
+    pub failed: usize,
+}
+
+impl Stats {
+    pub fn add_map(&mut self, len: usize) {
+        self.maps += 1;
+        self.bytes += len as u64;
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.maps += rhs.maps;
+        self.bytes += rhs.bytes;
+        self.skipped += rhs.skipped;
+        self.failed += rhs.failed;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} map(s), {} byte(s), {} skipped",
+            self.maps, self.bytes, self.skipped
+        )
+    }
+}
+
+/// The result of a run.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Report {
+    pub downloaded: usize,
+    pub skipped: usize,
+    /// Number of editions, or of maps of a map list, which failed to download.
+    pub failed: usize,
+    pub bytes: u64,
+    #[serde(rename = "elapsed_secs", serialize_with = "serialize_secs")]
+    pub elapsed: Duration,
+    /// Highest number of requests to MX in flight at the same time.
+    pub peak_concurrency: usize,
+    /// Highest number of bytes of downloaded maps held in memory at the same time.
+    pub peak_memory_bytes: u64,
+    /// Bytes received from each host, including the metadata and the thumbnails.
+    pub bytes_per_host: BTreeMap<String, u64>,
+    /// The maps not downloaded, with the reason, including the ones dropped by the filters.
+    pub skipped_maps: Vec<Skip>,
+}
+
+fn serialize_secs<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+impl Report {
+    pub fn new(
+        stats: Stats,
+        elapsed: Duration,
+        peak_concurrency: usize,
+        peak_memory_bytes: u64,
+        bytes_per_host: BTreeMap<String, u64>,
+        skipped_maps: Vec<Skip>,
+    ) -> Self {
+        Self {
+            downloaded: stats.maps,
+            skipped: stats.skipped,
+            failed: stats.failed,
+            bytes: stats.bytes,
+            elapsed,
+            peak_concurrency,
+            peak_memory_bytes,
+            bytes_per_host,
+            skipped_maps,
+        }
+    }
+
+    /// Returns the number of skipped maps of each reason, and their UIDs.
+    pub fn skips_summary(&self) -> Option<String> {
+        let mut by_reason = BTreeMap::<_, Vec<_>>::new();
+        for skip in &self.skipped_maps {
+            by_reason
+                .entry(skip.reason)
+                .or_default()
+                .push(skip.map_uid.as_str());
+        }
+        (!by_reason.is_empty()).then(|| {
+            by_reason
+                .into_iter()
+                .map(|(reason, uids)| format!("{} {reason} ({})", uids.len(), uids.join(", ")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} map(s), {} byte(s), {} skipped, {} failed edition(s) in {:.1?}, \
+            at most {} request(s) in flight and {} byte(s) in memory",
+            self.downloaded,
+            self.bytes,
+            self.skipped,
+            self.failed,
+            self.elapsed,
+            self.peak_concurrency,
+            self.peak_memory_bytes
+        )?;
+        for (host, bytes) in &self.bytes_per_host {
+            write!(f, ", {bytes} byte(s) from {host}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the requests in flight, and remembers the highest count.
+#[derive(Default)]
+pub struct InFlight {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+pub struct InFlightGuard<'a>(&'a InFlight);
+
+impl InFlight {
+    pub fn enter(&self) -> InFlightGuard<'_> {
+        let current = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+        InFlightGuard(self)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts the bytes of the downloaded maps held in memory until they're written, and makes the
+/// new downloads wait while they would go over `--max-inflight-bytes`.
+pub struct Memory {
+    cap: Option<u64>,
+    held: AtomicU64,
+    peak: AtomicU64,
+    /// The downloads admitted and not finished yet, whose size isn't known.
+    in_progress: AtomicU64,
+    largest: AtomicU64,
+    /// Admits the downloads in the order they arrive, so that a map isn't held back by the
+    /// ones after it.
+    turnstile: tokio::sync::Semaphore,
+    freed: tokio::sync::Notify,
+}
+
+/// A download admitted by [`Memory::admit`], until its content is held.
+pub struct Admission(Arc<Memory>);
+
+/// Bytes held in memory, released when dropped.
+pub struct MemoryGuard {
+    memory: Arc<Memory>,
+    bytes: u64,
+}
+
+impl Memory {
+    pub fn new(cap: Option<u64>) -> Self {
+        Self {
+            cap,
+            held: AtomicU64::new(0),
+            peak: AtomicU64::new(0),
+            in_progress: AtomicU64::new(0),
+            largest: AtomicU64::new(0),
+            turnstile: tokio::sync::Semaphore::new(1),
+            freed: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Whether a new download can start, the ones in progress are counted as large as the
+    /// largest map so far. A download can always start under the cap if it's the only one,
+    /// whatever its size.
+    fn has_room(&self, cap: u64) -> bool {
+        let held = self.held.load(Ordering::Relaxed);
+        let in_progress = self.in_progress.load(Ordering::Relaxed);
+        let largest = self.largest.load(Ordering::Relaxed);
+        if in_progress == 0 {
+            held < cap
+        } else {
+            largest > 0 && held + (in_progress + 1) * largest <= cap
+        }
+    }
+
+    /// Waits until a download can start without going over the cap, if any.
+    pub async fn admit(self: &Arc<Self>) -> Admission {
+        if let Some(cap) = self.cap {
+            if let Ok(_turn) = self.turnstile.acquire().await {
+                loop {
+                    // Created before checking, so that a release in between isn't missed
+                    let freed = self.freed.notified();
+                    if self.has_room(cap) {
+                        break;
+                    }
+                    freed.await;
+                }
+            }
+        }
+        self.in_progress.fetch_add(1, Ordering::Relaxed);
+        Admission(self.clone())
+    }
+
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+impl Admission {
+    pub fn hold(self, bytes: u64) -> MemoryGuard {
+        let memory = self.0.clone();
+        let held = memory.held.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        memory.peak.fetch_max(held, Ordering::Relaxed);
+        memory.largest.fetch_max(bytes, Ordering::Relaxed);
+        MemoryGuard { memory, bytes }
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.0.in_progress.fetch_sub(1, Ordering::Relaxed);
+        self.0.freed.notify_waiters();
+    }
+}
+
+impl Drop for MemoryGuard {
+    fn drop(&mut self) {
+        self.memory.held.fetch_sub(self.bytes, Ordering::Relaxed);
+        self.memory.freed.notify_waiters();
+    }
+}
+
+/// Counts the bytes received from each host.
+#[derive(Default)]
+pub struct Bandwidth(Mutex<BTreeMap<String, u64>>);
+
+impl Bandwidth {
+    pub fn add(&self, url: &reqwest::Url, bytes: usize) {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => url.scheme().to_owned(),
+        };
+        *self.0.lock().unwrap().entry(host).or_default() += bytes as u64;
+    }
+
+    pub fn per_host(&self) -> BTreeMap<String, u64> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Why a map of the edition wasn't downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Already in the output, or listed by the manifest resumed
+    AlreadyPresent,
+    /// Not modified on MX since the previous run, with `--conditional`
+    Unchanged,
+    /// Not selected by `--include-map`, `--include-map-glob`, `--limit` or `--limit-categories`
+    FilteredOut,
+    /// Not found on MX by `--head-check`
+    NotOnMx,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AlreadyPresent => "already present",
+            Self::Unchanged => "unchanged",
+            Self::FilteredOut => "filtered out",
+            Self::NotOnMx => "not found on MX",
+        })
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Skip {
+    pub category: String,
+    pub map_uid: String,
+    pub reason: SkipReason,
+}
+
+/// Collects the skipped maps of the run.
+#[derive(Default)]
+pub struct Skips(Mutex<Vec<Skip>>);
+
+impl Skips {
+    pub fn add(&self, category: &str, map_uid: &str, reason: SkipReason) {
+        self.0.lock().unwrap().push(Skip {
+            category: category.to_owned(),
+            map_uid: map_uid.to_owned(),
+            reason,
+        });
+    }
+
+    pub fn list(&self) -> Vec<Skip> {
+        self.0.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{fetch_edition, map_url, resolve_edition, select_maps, Context, Map, MxId};
+
+/// Prints the URL of the map `mx_id`, or of the maps of the edition selected by the filters.
+pub async fn run(
+    ctx: &Context,
+    mx_id: Option<MxId>,
+    event_handle: Option<String>,
+    event_edition: Option<u32>,
+) -> anyhow::Result<()> {
+    if let Some(mx_id) = mx_id {
+        println!(
+            "{}",
+            map_url(
+                &ctx.args,
+                &Map {
+                    mx_id,
+                    map_uid: String::new(),
+                    mx_version: None,
+                    download_url: None,
+                    sha256: None,
+                    name: None,
+                    index: None,
+                    mx_file_name: None,
+                }
+            )?
+        );
+        return Ok(());
+    }
+
+    let (event_handle, event_edition) = resolve_edition(ctx, event_handle, event_edition).await?;
+    // Without writing any file, the paths can collide
+    let mut event = fetch_edition(ctx, &event_handle, event_edition, None).await?;
+    select_maps(ctx, &mut event)?;
+    for map in event.categories.iter().flat_map(|cat| &cat.maps) {
+        println!("{}", map_url(&ctx.args, map)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Records the responses of the API and MX to fixture files with `--record`, and answers the
+//! requests with them with `--replay`, so that the tests don't depend on the network.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context as _;
+use reqwest::ResponseBuilderExt as _;
+use sha2::Digest as _;
+
+use crate::log;
+
+#[derive(Clone)]
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+/// Set from `--record` or `--replay` when the context is created.
+static MODE: Mutex<Option<Mode>> = Mutex::new(None);
+
+pub fn set_mode(mode: Option<Mode>) {
+    *MODE.lock().unwrap() = mode;
+}
+
+/// A recorded response, stored in `<key>.json` next to its body in `<key>.body`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Fixture {
+    method: String,
+    /// The URL of the request, without its credentials.
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    final_url: String,
+}
+
+/// Returns the name of the fixture files of a request, from its method, its URL and the ETag
+/// it sends.
+fn key(req: &reqwest::Request) -> String {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(" ");
+    hasher.update(req.url().as_str());
+    if let Some(etag) = req.headers().get(reqwest::header::IF_NONE_MATCH) {
+        hasher.update(" ");
+        hasher.update(etag.as_bytes());
+    }
+    hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn response(fixture: &Fixture, body: Vec<u8>) -> reqwest::Response {
+    let mut builder = http::Response::builder().status(fixture.status);
+    if let Ok(url) = reqwest::Url::parse(&fixture.final_url) {
+        builder = builder.url(url);
+    }
+    for (name, value) in &fixture.headers {
+        builder = builder.header(name, value);
+    }
+    builder
+        .body(body)
+        .unwrap_or_else(|_| {
+            log::error!("Invalid fixture for `{} {}`", fixture.method, fixture.url);
+            let mut res = http::Response::new(Vec::new());
+            *res.status_mut() = reqwest::StatusCode::INTERNAL_SERVER_ERROR;
+            res
+        })
+        .into()
+}
+
+fn save(dir: &Path, key: &str, fixture: &Fixture, body: &[u8]) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create directory `{}`", dir.display()))?;
+    let json = serde_json::to_vec_pretty(fixture).context("Unable to serialize fixture")?;
+    crate::output::write_file(&dir.join(format!("{key}.json")), json)?;
+    crate::output::write_file(&dir.join(format!("{key}.body")), body)
+}
+
+fn load(dir: &Path, key: &str) -> anyhow::Result<(Fixture, Vec<u8>)> {
+    let path = dir.join(format!("{key}.json"));
+    let json =
+        std::fs::read(&path).with_context(|| format!("Unable to read `{}`", path.display()))?;
+    let fixture = serde_json::from_slice(&json)
+        .with_context(|| format!("Invalid fixture `{}`", path.display()))?;
+    let path = dir.join(format!("{key}.body"));
+    let body =
+        std::fs::read(&path).with_context(|| format!("Unable to read `{}`", path.display()))?;
+    Ok((fixture, body))
+}
+
+/// Sends the request, or answers it with its fixture when replaying.
+///
+/// A request without fixture is answered `404 Not Found`, so that it fails the same way at
+/// each run.
+pub async fn execute(
+    client: &reqwest::Client,
+    req: reqwest::Request,
+) -> reqwest::Result<reqwest::Response> {
+    let mode = MODE.lock().unwrap().clone();
+    match mode {
+        None => client.execute(req).await,
+        Some(Mode::Replay(dir)) => {
+            let key = key(&req);
+            Ok(match load(&dir, &key) {
+                Ok((fixture, body)) => response(&fixture, body),
+                Err(e) => {
+                    log::error!(
+                        "No recorded response for `{} {}`: {e:#}",
+                        req.method(),
+                        crate::curl::redacted_url(req.url())
+                    );
+                    let mut res = http::Response::new(Vec::new());
+                    *res.status_mut() = reqwest::StatusCode::NOT_FOUND;
+                    res.into()
+                }
+            })
+        }
+        Some(Mode::Record(dir)) => {
+            let key = key(&req);
+            let method = req.method().to_string();
+            let url = crate::curl::redacted_url(req.url()).to_string();
+            let res = client.execute(req).await?;
+            let fixture = Fixture {
+                method,
+                url,
+                status: res.status().as_u16(),
+                headers: res
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                        (name.to_string(), value)
+                    })
+                    .collect(),
+                final_url: res.url().to_string(),
+            };
+            let body = res.bytes().await?.to_vec();
+            if let Err(e) = save(&dir, &key, &fixture, &body) {
+                log::error!("Unable to record the response of `{}`: {e:#}", fixture.url);
+            }
+            Ok(response(&fixture, body))
+        }
+    }
+}
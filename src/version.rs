@@ -0,0 +1,34 @@
+use crate::{Command, API_HOST, MX_HOST};
+
+const FEATURES: &[(&str, bool)] = &[
+    ("tracing", cfg!(feature = "tracing")),
+    ("localhost_test", cfg!(feature = "localhost_test")),
+];
+
+pub fn run(args: &Command) {
+    println!("soevent {}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "Build:        {}",
+        if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        }
+    );
+    let features = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    if features.is_empty() {
+        println!("Features:     none");
+    } else {
+        println!("Features:     {}", features.join(", "));
+    }
+    println!("API base URL: {API_HOST}");
+    if crate::api_host(args) != API_HOST {
+        println!("  overridden: {}", crate::api_host(args));
+    }
+    println!("MX base URL:  {MX_HOST}");
+    println!("Configuration: {args:#?}");
+}